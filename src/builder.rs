@@ -0,0 +1,176 @@
+// chainable construction for computers with many options; anything left
+// unset keeps the plain constructor's default
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use crate::{AcceptPolicy, Client, GreaterThan, Id, Server, Speculation};
+
+pub struct ClientBuilder {
+    client: Client,
+}
+
+impl ClientBuilder {
+    pub fn new(index: usize, n_servers: usize) -> ClientBuilder {
+        ClientBuilder {
+            client: Client::new(index, n_servers),
+        }
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> ClientBuilder {
+        self.client.max_retries(Some(max_retries));
+        self
+    }
+
+    pub fn on_commit(mut self, hook: impl FnMut(Id) + Send + 'static) -> ClientBuilder {
+        self.client.on_commit(hook);
+        self
+    }
+
+    pub fn speculate(mut self, hook: impl FnMut(Speculation) + Send + 'static) -> ClientBuilder {
+        self.client.speculate(hook);
+        self
+    }
+
+    pub fn two_phase(mut self) -> ClientBuilder {
+        self.client.two_phase(true);
+        self
+    }
+
+    pub fn share_hint(mut self, hint: Arc<AtomicU64>) -> ClientBuilder {
+        self.client.share_hint(hint);
+        self
+    }
+
+    pub fn build(self) -> Client {
+        self.client
+    }
+}
+
+pub struct ServerBuilder {
+    max_id: Id,
+    policy: Box<dyn AcceptPolicy>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> ServerBuilder {
+        ServerBuilder {
+            max_id: 0,
+            policy: Box::new(GreaterThan),
+        }
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    pub fn max_id(mut self, max_id: Id) -> ServerBuilder {
+        self.max_id = max_id;
+        self
+    }
+
+    pub fn policy(mut self, policy: impl AcceptPolicy + 'static) -> ServerBuilder {
+        self.policy = Box::new(policy);
+        self
+    }
+
+    pub fn build(self) -> Server {
+        Server {
+            max_id: self.max_id,
+            policy: self.policy,
+            ..Server::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Mutex;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{Computer, Message, StrictNext};
+
+    // whether the server takes an id `gap` above its max_id
+    fn accepts(server: &mut Server, gap: Id) -> bool {
+        let id = server.max_id() + gap;
+        match server.propose(3, Uuid::new_v4(), (1, 3), id).unwrap()[0].1 {
+            Message::Response { success, .. } => success,
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn client_builder_applies_every_option() {
+        let hint = Arc::new(AtomicU64::new(0));
+        let committed = Arc::new(Mutex::new(vec![]));
+        let speculated = Arc::new(Mutex::new(vec![]));
+        let (commit_log, speculation_log) = (committed.clone(), speculated.clone());
+        let client = ClientBuilder::new(5, 5)
+            .max_retries(2)
+            .on_commit(move |id| commit_log.lock().unwrap().push(id))
+            .speculate(move |speculation| speculation_log.lock().unwrap().push(speculation))
+            .two_phase()
+            .share_hint(hint.clone())
+            .build();
+        assert_eq!(client.max_retries, Some(2));
+        assert_eq!(client.ballot, (0, 5));
+
+        let mut computers: Vec<Computer> = (0..5)
+            .map(|_| Computer::Server(Server::default()))
+            .collect();
+        computers.push(Computer::Client(client));
+
+        // the hint is shared, not copied, so the round starts above it
+        hint.store(40, Ordering::Relaxed);
+        let outbound = match &mut computers[5] {
+            Computer::Client(client) => client.generate_requests(),
+            Computer::Server(_) => unreachable!(),
+        };
+        // one message to each of the five servers, preparing first
+        assert_eq!(outbound.len(), 5);
+        assert!(matches!(outbound[0].1, Message::Prepare { .. }));
+
+        let mut queue: Vec<(usize, usize, Message)> = outbound
+            .into_iter()
+            .map(|(to, message)| (5, to, message))
+            .collect();
+        while !queue.is_empty() {
+            let (from, to, message) = queue.remove(0);
+            for (next, message) in computers[to].receive(from, message).unwrap() {
+                queue.push((to, next, message));
+            }
+        }
+
+        assert_eq!(*committed.lock().unwrap(), vec![41]);
+        assert_eq!(
+            *speculated.lock().unwrap(),
+            vec![Speculation::Issued(41), Speculation::Confirmed(41)]
+        );
+    }
+
+    #[test]
+    fn unset_options_keep_the_defaults() {
+        let client = ClientBuilder::new(3, 5).build();
+        assert_eq!(client.max_retries, None);
+        assert!(!client.two_phase);
+        assert!(client.hint.is_none());
+
+        let mut server = ServerBuilder::new().build();
+        assert_eq!(server.max_id(), 0);
+        // GreaterThan takes a gap
+        assert!(accepts(&mut server, 2));
+    }
+
+    #[test]
+    fn server_builder_applies_every_option() {
+        let mut server = ServerBuilder::new().max_id(7).policy(StrictNext).build();
+        assert_eq!(server.max_id(), 7);
+        assert!(!accepts(&mut server, 2));
+        assert!(accepts(&mut server, 1));
+        assert_eq!(server.max_id(), 8);
+    }
+}
@@ -0,0 +1,303 @@
+// runs every computer on its own thread, wired together with one mpsc
+// channel per node, for embedding without the simulator or a real network
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::transport::{self, Transport};
+use crate::{Computer, ConfigError, From, Id, Message, To, Violation};
+
+// a client that hears nothing for this long starts a fresh round
+const RETRY_AFTER: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    // rejected before any thread was started
+    Config(ConfigError),
+    Violation(Violation),
+}
+
+impl std::convert::From<Violation> for Error {
+    fn from(violation: Violation) -> Error {
+        Error::Violation(violation)
+    }
+}
+
+enum Envelope {
+    Deliver(From, Message),
+    Shutdown,
+}
+
+// nodes are addressed by their position in `computers`, so servers must come
+// first, matching the indices clients send to. each client allocates a single
+// id, or none if it runs out of retries; an on_commit hook it already has is
+// still called. returns the committed ids in the order they completed. a
+// node that hits a Violation stops, and the first one reported is returned
+// once everything has been joined. without any server clients would retry
+// forever, so that is refused up front like in Cluster::new.
+pub fn run(computers: Vec<Computer>) -> Result<Vec<Id>, Error> {
+    if !computers
+        .iter()
+        .any(|computer| matches!(computer, Computer::Server(_)))
+    {
+        return Err(Error::Config(ConfigError::NoServers));
+    }
+
+    let (outboxes, inboxes): (Vec<Sender<Envelope>>, Vec<Receiver<Envelope>>) =
+        computers.iter().map(|_| mpsc::channel()).unzip();
+    let (commits_tx, commits_rx) = mpsc::channel();
+
+    let mut servers = vec![];
+    let mut clients = vec![];
+
+    for (index, (computer, inbox)) in computers.into_iter().zip(inboxes).enumerate() {
+        let mut transport = ChannelTransport {
+            index,
+            inbox,
+            outboxes: outboxes.clone(),
+            timeout: None,
+        };
+        match computer {
+            Computer::Server(_) => {
+                let handle = thread::spawn(move || serve(computer, transport));
+                servers.push((index, handle));
+            }
+            Computer::Client(_) => {
+                transport.timeout = Some(RETRY_AFTER);
+                let commits = commits_tx.clone();
+                clients.push(thread::spawn(move || {
+                    allocate(computer, transport, commits)
+                }));
+            }
+        }
+    }
+    drop(commits_tx);
+
+    let mut outcome = Ok(());
+    for client in clients {
+        let result = client.join().expect("client thread panicked");
+        outcome = outcome.and(result);
+    }
+    for (index, server) in servers {
+        let _ = outboxes[index].send(Envelope::Shutdown);
+        let result = server.join().expect("server thread panicked");
+        outcome = outcome.and(result);
+    }
+
+    outcome?;
+    Ok(commits_rx.iter().collect())
+}
+
+// one node's end of the channels
+struct ChannelTransport {
+    index: usize,
+    inbox: Receiver<Envelope>,
+    outboxes: Vec<Sender<Envelope>>,
+    // recv gives up after this long; None waits until shut down
+    timeout: Option<Duration>,
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, to: To, message: Message) {
+        // the peer may already have finished and hung up
+        let _ = self.outboxes[to].send(Envelope::Deliver(self.index, message));
+    }
+
+    fn recv(&mut self) -> Option<(From, Message)> {
+        let envelope = match self.timeout {
+            Some(timeout) => self.inbox.recv_timeout(timeout).ok(),
+            None => self.inbox.recv().ok(),
+        };
+        match envelope {
+            Some(Envelope::Deliver(from, message)) => Some((from, message)),
+            Some(Envelope::Shutdown) | None => None,
+        }
+    }
+}
+
+fn serve(mut computer: Computer, mut transport: ChannelTransport) -> Result<(), Violation> {
+    while transport::step(&mut computer, &mut transport)? {}
+    Ok(())
+}
+
+fn allocate(
+    mut computer: Computer,
+    mut transport: ChannelTransport,
+    commits: Sender<Id>,
+) -> Result<(), Violation> {
+    let (committed_tx, committed_rx) = mpsc::channel();
+    let client = if let Computer::Client(client) = &mut computer {
+        client
+    } else {
+        unreachable!()
+    };
+    let mut caller_hook = client.on_commit.take();
+    client.on_commit(move |id| {
+        if let Some(hook) = &mut caller_hook {
+            hook(id);
+        }
+        let _ = committed_tx.send(id);
+    });
+
+    let outbound = client.generate_requests();
+    transport::send_all(&mut transport, outbound);
+
+    loop {
+        if !transport::step(&mut computer, &mut transport)? {
+            // heard nothing for RETRY_AFTER
+            if let Computer::Client(client) = &mut computer {
+                let outbound = client.retry();
+                transport::send_all(&mut transport, outbound);
+            }
+        }
+
+        if let Ok(id) = committed_rx.try_recv() {
+            let _ = commits.send(id);
+            return Ok(());
+        }
+        if let Computer::Client(client) = &computer {
+            // ran out of retries; leave without an id
+            if client.error().is_some() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{AcceptDecision, AcceptPolicy, Client, GreaterThan, Server, ServerBuilder};
+
+    fn computers(n_servers: usize, n_clients: usize) -> Vec<Computer> {
+        let mut computers: Vec<Computer> = (0..n_servers)
+            .map(|_| Computer::Server(Server::default()))
+            .collect();
+        for index in n_servers..n_servers + n_clients {
+            let mut client = Client::new(index, n_servers);
+            client.max_retries(Some(1_000));
+            computers.push(Computer::Client(client));
+        }
+        computers
+    }
+
+    #[test]
+    fn threads_allocate_unique_ids() {
+        let mut ids = run(computers(5, 8)).unwrap();
+        assert_eq!(ids.len(), 8);
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 8);
+    }
+
+    // GreaterThan, also keeping the highest id accepted by any server
+    // sharing it, since run doesn't hand the servers back
+    #[derive(Debug)]
+    struct Recording(Arc<AtomicU64>);
+
+    impl AcceptPolicy for Recording {
+        fn accept(&mut self, from: From, uuid: Uuid, id: Id, current_max: Id) -> AcceptDecision {
+            let decision = GreaterThan.accept(from, uuid, id, current_max);
+            if decision == AcceptDecision::Accept {
+                self.0.fetch_max(id, Ordering::Relaxed);
+            }
+            decision
+        }
+    }
+
+    // distinct, and each in (start, max_id]: above where the servers
+    // started and accepted by at least one of them
+    fn check_ids(mut ids: Vec<Id>, start: Id, max_id: Id) -> usize {
+        ids.sort_unstable();
+        let n = ids.len();
+        ids.dedup();
+        assert_eq!(ids.len(), n, "an id was committed twice");
+        assert!(
+            ids.iter().all(|id| *id > start && *id <= max_id),
+            "{:?}",
+            ids
+        );
+        n
+    }
+
+    #[test]
+    fn same_scenario_over_both_transports() {
+        // one client: every schedule commits the same id
+        let mut cluster = crate::Cluster::new_at(3, 1, 41).unwrap();
+        cluster.seed(0);
+        cluster.run().unwrap();
+        let simulated: Vec<Id> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| commit.id)
+            .collect();
+
+        let mut nodes: Vec<Computer> = (0..3)
+            .map(|_| Computer::Server(Server::new_at(41)))
+            .collect();
+        nodes.push(Computer::Client(Client::new(3, 3)));
+        assert_eq!(run(nodes).unwrap(), simulated);
+        assert_eq!(simulated, vec![42]);
+
+        // contended: rounds that miss quorum burn ids depending on the
+        // schedule, so the ids can differ, but not what holds of them
+        let mut cluster = crate::Cluster::new_at(5, 4, 10).unwrap();
+        cluster.seed(3);
+        cluster.run().unwrap();
+        let simulated: Vec<Id> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| commit.id)
+            .collect();
+        let max_id = (0..5)
+            .map(|index| cluster.server(index).unwrap().max_id())
+            .max()
+            .unwrap();
+
+        let accepted = Arc::new(AtomicU64::new(10));
+        let mut nodes: Vec<Computer> = (0..5)
+            .map(|_| {
+                let server = ServerBuilder::new()
+                    .max_id(10)
+                    .policy(Recording(accepted.clone()))
+                    .build();
+                Computer::Server(server)
+            })
+            .collect();
+        for index in 5..9 {
+            let mut client = Client::new(index, 5);
+            client.max_retries(Some(1_000));
+            nodes.push(Computer::Client(client));
+        }
+        let threaded = run(nodes).unwrap();
+
+        assert_eq!(check_ids(simulated, 10, max_id), 4);
+        assert_eq!(check_ids(threaded, 10, accepted.load(Ordering::Relaxed)), 4);
+    }
+
+    #[test]
+    fn no_servers_is_refused() {
+        assert_eq!(
+            run(computers(0, 2)),
+            Err(Error::Config(ConfigError::NoServers))
+        );
+    }
+
+    #[test]
+    fn caller_hook_is_kept() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut computers = computers(3, 1);
+        if let Computer::Client(client) = &mut computers[3] {
+            let seen = seen.clone();
+            client.on_commit(move |id| seen.lock().unwrap().push(id));
+        }
+
+        let ids = run(computers).unwrap();
+        assert_eq!(*seen.lock().unwrap(), ids);
+    }
+}
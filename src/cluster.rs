@@ -0,0 +1,1395 @@
+// fake cluster: every computer in one process, messages delivered in random order
+use std::io::{self, Write};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use uuid::Uuid;
+
+use crate::transport::{self, Transport};
+use crate::{
+    AcceptPolicy, Client, Computer, From, Id, IdSink, Message, Reference, Server, To, Violation,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    // no acceptors at all, so no quorum can ever be formed
+    NoServers,
+    // a loss ratio whose numerator exceeds its denominator, or out of 0
+    LossRatio,
+}
+
+// a committed id, which client reached quorum on it, when, and in which round
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub id: Id,
+    pub client: usize,
+    pub tick: u64,
+    pub uuid: Uuid,
+    // the servers whose accepts made up the quorum, in arrival order;
+    // accepts that arrived after the commit are not included
+    pub acceptors: Vec<usize>,
+}
+
+// an attempt to move the cluster's clock backward
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockRewind {
+    pub now: u64,
+    pub attempted: u64,
+}
+
+// a client left mid-round once nothing more was delivered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stranded {
+    pub client: usize,
+    pub uuid: Uuid,
+    pub id: Id,
+}
+
+// layouts for export_commits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    // header row, then one row per commit; acceptors are joined with ';'
+    Csv,
+    // one JSON object per line
+    JsonLines,
+}
+
+// the separate sources of randomness in a run. each is derived from the
+// master seed on its own, so drawing more from one, or adding another,
+// leaves the others' decisions for that seed unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngStream {
+    // whether a sent message is dropped
+    Loss,
+    // delivery order
+    Scheduling,
+    // round uuids, one stream per client
+    Uuid,
+}
+
+// how many times each stream was called on: one gen_ratio per loss check,
+// one gen_range per swap while shuffling, one per round uuid. these count
+// calls, not the words the rng produced underneath, which a single call may
+// take more of. a change that should leave randomness alone must leave these
+// alone for the same seed and workload.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RngUsage {
+    pub loss: u64,
+    pub scheduling: u64,
+    pub uuid: u64,
+}
+
+impl RngStream {
+    // `index` tells apart streams of the same kind, e.g. per client
+    fn seed(self, seed: u64, index: u64) -> u64 {
+        let stream = self as u64 + 1;
+        seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ index.wrapping_mul(0xbf58_476d_1ce4_e5b9)
+    }
+}
+
+// assigned to each message when it is enqueued, unique and increasing
+pub type MsgSeq = u64;
+
+#[derive(Debug)]
+struct Network {
+    in_flight: Vec<(MsgSeq, From, To, Message)>,
+    next_seq: MsgSeq,
+    // pinned out of delivery until released
+    held: Vec<MsgSeq>,
+    // drop this many out of every so many sent messages; None never drops
+    loss_ratio: Option<(u32, u32)>,
+    lost: u64,
+    loss: StdRng,
+    scheduling: StdRng,
+    usage: RngUsage,
+}
+
+impl Network {
+    fn new(loss: StdRng, scheduling: StdRng) -> Network {
+        Network {
+            in_flight: vec![],
+            next_seq: 0,
+            held: vec![],
+            loss_ratio: None,
+            lost: 0,
+            loss,
+            scheduling,
+            usage: RngUsage::default(),
+        }
+    }
+
+    // fisher-yates from the back: for i = len-1 down to 1, swap i with an
+    // index drawn uniformly from 0..=i as a u64. the permutation depends only
+    // on the scheduling rng and the queue length, never on the messages, and
+    // draws the same way on every platform.
+    fn shuffle(&mut self) {
+        for i in (1..self.in_flight.len()).rev() {
+            let j = self.scheduling.gen_range(0, i as u64 + 1) as usize;
+            self.usage.scheduling += 1;
+            self.in_flight.swap(i, j);
+        }
+    }
+
+    fn send(&mut self, from: From, to: To, message: Message) {
+        if let Some((numerator, denominator)) = self.loss_ratio {
+            self.usage.loss += 1;
+            if self.loss.gen_ratio(numerator, denominator) {
+                self.lost += 1;
+                return;
+            }
+        }
+        self.in_flight.push((self.next_seq, from, to, message));
+        self.next_seq += 1;
+    }
+
+    // the last message in the queue that isn't held
+    fn next(&mut self) -> Option<(MsgSeq, From, To, Message)> {
+        let held = &self.held;
+        let index = self
+            .in_flight
+            .iter()
+            .rposition(|(seq, ..)| !held.contains(seq))?;
+        Some(self.in_flight.remove(index))
+    }
+}
+
+// what a single delivery looks like to the computer receiving it
+struct Delivery<'a> {
+    node: To,
+    message: Option<(From, Message)>,
+    network: &'a mut Network,
+}
+
+impl Transport for Delivery<'_> {
+    fn send(&mut self, to: To, message: Message) {
+        self.network.send(self.node, to, message);
+    }
+
+    fn recv(&mut self) -> Option<(From, Message)> {
+        self.message.take()
+    }
+}
+
+#[derive(Debug)]
+pub struct Cluster {
+    n_servers: usize,
+    // max_id every server started from
+    start_id: Id,
+    computers: Vec<Computer>,
+    network: Network,
+    // ids each client allocates, one after another
+    allocations: u64,
+
+    // print every delivered message
+    trace: bool,
+
+    // one tick per delivered message
+    tick: u64,
+    commit_log: Vec<Commit>,
+    sink: Option<Box<dyn IdSink>>,
+    sink_error: Option<io::Error>,
+
+    // messages addressed to a node index that doesn't exist
+    stale_target_dropped: u64,
+}
+
+impl Cluster {
+    // servers take indices 0..n_servers, clients the ones after. zero
+    // servers is a ConfigError; zero clients is allowed, and run then
+    // returns straight away with nothing committed.
+    pub fn new(n_servers: usize, n_clients: usize) -> Result<Cluster, ConfigError> {
+        Cluster::new_at(n_servers, n_clients, 0)
+    }
+
+    // every server starts with the given max_id; clients catch up to it
+    // through their first rejected round
+    pub fn new_at(n_servers: usize, n_clients: usize, max_id: Id) -> Result<Cluster, ConfigError> {
+        if n_servers == 0 {
+            return Err(ConfigError::NoServers);
+        }
+
+        let mut computers = vec![];
+        for _ in 0..n_servers {
+            computers.push(Computer::Server(Server::new_at(max_id)));
+        }
+        for index in n_servers..n_servers + n_clients {
+            computers.push(Computer::Client(Client::new(index, n_servers)));
+        }
+
+        Ok(Cluster {
+            n_servers,
+            start_id: max_id,
+            computers,
+            network: Network::new(StdRng::from_entropy(), StdRng::from_entropy()),
+            allocations: 1,
+            trace: false,
+            tick: 0,
+            commit_log: vec![],
+            sink: None,
+            sink_error: None,
+            stale_target_dropped: 0,
+        })
+    }
+
+    // servers that can fail with a majority still left. zero for a single
+    // server, which is a plain counter, and also for n_servers == 2, where the
+    // majority is both of them and losing either one stalls every client.
+    pub fn fault_tolerance(&self) -> usize {
+        self.n_servers - crate::Durability::Quorum.accepts_needed(self.n_servers)
+    }
+
+    // makes loss, delivery order and round uuids reproducible; call before
+    // run
+    pub fn seed(&mut self, seed: u64) {
+        self.network.loss = StdRng::seed_from_u64(RngStream::Loss.seed(seed, 0));
+        self.network.scheduling = StdRng::seed_from_u64(RngStream::Scheduling.seed(seed, 0));
+        for (index, computer) in self.computers.iter_mut().enumerate() {
+            if let Computer::Client(client) = computer {
+                client.seed_uuids(RngStream::Uuid.seed(seed, index as u64));
+            }
+        }
+    }
+
+    // every message sent from now on, first requests included, is dropped
+    // with probability numerator/denominator. off unless set; (0, _) turns
+    // it off again.
+    pub fn loss(&mut self, numerator: u32, denominator: u32) -> Result<(), ConfigError> {
+        if denominator == 0 || numerator > denominator {
+            return Err(ConfigError::LossRatio);
+        }
+        self.network.loss_ratio = if numerator == 0 {
+            None
+        } else {
+            Some((numerator, denominator))
+        };
+        Ok(())
+    }
+
+    // messages dropped by the loss ratio
+    pub fn lost(&self) -> u64 {
+        self.network.lost
+    }
+
+    // each client starts its next allocation as soon as one commits, until
+    // it has `per_client` ids, so load keeps arriving for the whole run.
+    // one unless set; zero counts as one. call before run.
+    pub fn allocations(&mut self, per_client: u64) {
+        self.allocations = per_client.max(1);
+    }
+
+    fn commits_by(&self, client: usize) -> u64 {
+        self.commit_log
+            .iter()
+            .filter(|commit| commit.client == client)
+            .count() as u64
+    }
+
+    pub fn trace(&mut self, enabled: bool) {
+        self.trace = enabled;
+    }
+
+    // messages still waiting for delivery, held ones included
+    pub fn in_flight(&self) -> impl Iterator<Item = (MsgSeq, From, To, &Message)> {
+        self.network
+            .in_flight
+            .iter()
+            .map(|(seq, from, to, message)| (*seq, *from, *to, message))
+    }
+
+    // keeps one message from being delivered, to script a specific race
+    pub fn hold(&mut self, seq: MsgSeq) {
+        if !self.network.held.contains(&seq) {
+            self.network.held.push(seq);
+        }
+    }
+
+    pub fn release(&mut self, seq: MsgSeq) {
+        self.network.held.retain(|held| *held != seq);
+    }
+
+    pub fn server(&self, index: usize) -> Option<&Server> {
+        match self.computers.get(index) {
+            Some(Computer::Server(server)) => Some(server),
+            _ => None,
+        }
+    }
+
+    pub fn server_mut(&mut self, index: usize) -> Option<&mut Server> {
+        match self.computers.get_mut(index) {
+            Some(Computer::Server(server)) => Some(server),
+            _ => None,
+        }
+    }
+
+    pub fn client_mut(&mut self, index: usize) -> Option<&mut Client> {
+        match self.computers.get_mut(index) {
+            Some(Computer::Client(client)) => Some(client),
+            _ => None,
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.tick
+    }
+
+    // later deliveries and commits are stamped from here on. the clock only
+    // moves forward, so commit ticks never go backward within one log; an
+    // earlier tick is refused and leaves the clock where it was.
+    pub fn set_now(&mut self, tick: u64) -> Result<(), ClockRewind> {
+        if tick < self.tick {
+            return Err(ClockRewind {
+                now: self.tick,
+                attempted: tick,
+            });
+        }
+        self.tick = tick;
+        Ok(())
+    }
+
+    // also deliver every committed id to `sink`
+    pub fn sink(&mut self, sink: impl IdSink + 'static) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    // why the sink failed, if it did. the run carries on without it, so the
+    // sink holds a prefix of the commit log and never one with a hole.
+    pub fn sink_error(&self) -> Option<&io::Error> {
+        self.sink_error.as_ref()
+    }
+
+    pub fn commit_log(&self) -> &[Commit] {
+        &self.commit_log
+    }
+
+    pub fn rng_usage(&self) -> RngUsage {
+        let uuid = self
+            .computers
+            .iter()
+            .map(|computer| match computer {
+                Computer::Client(client) => client.uuids_drawn(),
+                Computer::Server(_) => 0,
+            })
+            .sum();
+        RngUsage {
+            uuid,
+            ..self.network.usage
+        }
+    }
+
+    // writes the commit log out one record at a time, in commit order
+    pub fn export_commits(&self, mut writer: impl Write, format: ExportFormat) -> io::Result<()> {
+        if format == ExportFormat::Csv {
+            writeln!(writer, "id,client,tick,uuid,acceptors")?;
+        }
+        for commit in &self.commit_log {
+            let acceptors: Vec<String> = commit.acceptors.iter().map(|a| a.to_string()).collect();
+            match format {
+                ExportFormat::Csv => writeln!(
+                    writer,
+                    "{},{},{},{},{}",
+                    commit.id,
+                    commit.client,
+                    commit.tick,
+                    commit.uuid,
+                    acceptors.join(";")
+                )?,
+                ExportFormat::JsonLines => writeln!(
+                    writer,
+                    "{{\"id\":{},\"client\":{},\"tick\":{},\"uuid\":\"{}\",\"acceptors\":[{}]}}",
+                    commit.id,
+                    commit.client,
+                    commit.tick,
+                    commit.uuid,
+                    acceptors.join(",")
+                )?,
+            }
+        }
+        writer.flush()
+    }
+
+    pub fn stale_target_dropped(&self) -> u64 {
+        self.stale_target_dropped
+    }
+
+    // ids some server has moved past that nobody committed: proposals that
+    // were accepted somewhere but never reached quorum
+    pub fn wasted_ids(&self) -> u64 {
+        self.max_server_id() - self.start_id - self.commit_log.len() as u64
+    }
+
+    // the highest id any server has accepted, or the start if none has
+    fn max_server_id(&self) -> Id {
+        self.computers
+            .iter()
+            .filter_map(|computer| match computer {
+                Computer::Server(server) => Some(server.max_id()),
+                Computer::Client(_) => None,
+            })
+            .max()
+            .unwrap_or(self.start_id)
+    }
+
+    // runs until no messages are left in flight, or a computer reports that
+    // a protocol invariant broke
+    pub fn run(&mut self) -> Result<(), Violation> {
+        self.start();
+        self.drain()
+    }
+
+    // after run: a differential check against Reference. with one client and
+    // no loss every allocation runs alone and nothing is ever rejected, so
+    // the commits must be exactly the ids the reference hands out for as many
+    // allocations, in the same order. with contention or loss, rounds that
+    // miss quorum burn ids the reference never skips, so only its uniqueness
+    // is checked. either way no client may commit more than `allocations`
+    // ids, and none may be above every server's max_id, since a quorum
+    // accepted it.
+    pub fn check_against_reference(&self) -> Result<(), Violation> {
+        let committed: Vec<Id> = self.commit_log.iter().map(|commit| commit.id).collect();
+
+        let serial =
+            self.computers.len() - self.n_servers == 1 && self.network.loss_ratio.is_none();
+        if serial {
+            let reference = Reference::new_at(self.start_id);
+            let expected: Vec<Id> = committed.iter().map(|_| reference.allocate()).collect();
+            verify!(
+                committed == expected,
+                "serial run committed {:?}, reference allocated {:?}",
+                committed,
+                expected
+            );
+        }
+
+        let mut ids = committed.clone();
+        ids.sort_unstable();
+        ids.dedup();
+        verify!(
+            ids.len() == committed.len(),
+            "{} commits but only {} distinct ids",
+            committed.len(),
+            ids.len()
+        );
+
+        for index in self.n_servers..self.computers.len() {
+            verify!(
+                self.commits_by(index) <= self.allocations,
+                "client {} committed more than {} ids",
+                index,
+                self.allocations
+            );
+        }
+
+        let max_id = self.max_server_id();
+        if let Some(highest) = ids.last() {
+            verify!(
+                *highest <= max_id,
+                "id {} committed above every server's max_id {}",
+                highest,
+                max_id
+            );
+        }
+        Ok(())
+    }
+
+    // after run: quiescence only means nothing is left to deliver, so tell
+    // apart every client finishing from some losing their round's messages
+    pub fn assert_all_idle(&self) -> Result<(), Vec<Stranded>> {
+        let stranded: Vec<Stranded> = self
+            .computers
+            .iter()
+            .enumerate()
+            .filter_map(|(index, computer)| match computer {
+                Computer::Client(client) => client.pending_round().map(|(uuid, id)| Stranded {
+                    client: index,
+                    uuid,
+                    id,
+                }),
+                Computer::Server(_) => None,
+            })
+            .collect();
+        if stranded.is_empty() {
+            Ok(())
+        } else {
+            Err(stranded)
+        }
+    }
+
+    // gives up every stranded round, retracting any id it speculated, and
+    // returns them as assert_all_idle would have. only for when nothing held
+    // will be released any more.
+    pub fn abandon_stranded(&mut self) -> Vec<Stranded> {
+        let stranded = self.assert_all_idle().err().unwrap_or_default();
+        for Stranded { client, .. } in &stranded {
+            if let Some(client) = self.client_mut(*client) {
+                client.abandon();
+            }
+        }
+        stranded
+    }
+
+    // like run, but servers switch to a policy from `upgrade` one at a time,
+    // in index order, with `soak` deliveries between each switch and the
+    // next. the cluster keeps serving throughout, so for a while servers
+    // with the old and new policy answer the same rounds.
+    pub fn rolling_upgrade<P: AcceptPolicy + 'static>(
+        &mut self,
+        mut upgrade: impl FnMut() -> P,
+        soak: usize,
+    ) -> Result<(), Violation> {
+        self.start();
+        for index in 0..self.n_servers {
+            if let Computer::Server(server) = &mut self.computers[index] {
+                server.set_policy(upgrade());
+            }
+            for _ in 0..soak {
+                if !self.step()? {
+                    break;
+                }
+            }
+        }
+        self.drain()
+    }
+
+    // every client issues its first request; with no clients nothing is
+    // sent and drain has nothing to deliver
+    pub fn start(&mut self) {
+        for (sender, computer) in self.computers.iter_mut().enumerate().skip(self.n_servers) {
+            let client = if let Computer::Client(client) = computer {
+                client
+            } else {
+                unreachable!()
+            };
+
+            let outbound = client.generate_requests();
+
+            for (to, message) in outbound {
+                self.network.send(sender, to, message);
+            }
+        }
+    }
+
+    // delivers until nothing deliverable is left; held messages stay queued
+    pub fn drain(&mut self) -> Result<(), Violation> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    // delivers one message; false when nothing deliverable is in flight
+    pub fn step(&mut self) -> Result<bool, Violation> {
+        let (seq, from, to, message) = match self.network.next() {
+            Some(next) => next,
+            None => return Ok(false),
+        };
+
+        if self.trace {
+            println!("seq={} from={} to={} message={:?}", seq, from, to, message);
+        }
+        let computer = match self.computers.get_mut(to) {
+            Some(computer) => computer,
+            None => {
+                self.stale_target_dropped += 1;
+                return Ok(true);
+            }
+        };
+        let mut delivery = Delivery {
+            node: to,
+            message: Some((from, message)),
+            network: &mut self.network,
+        };
+        transport::step(computer, &mut delivery)?;
+        self.tick += 1;
+
+        if let Computer::Client(client) = &mut self.computers[to] {
+            if let Some((id, uuid, acceptors)) = client.take_commit() {
+                if let Some(sink) = &mut self.sink {
+                    if let Err(error) = sink.emit(id, to) {
+                        self.sink = None;
+                        self.sink_error = Some(error);
+                    }
+                }
+                self.commit_log.push(Commit {
+                    id,
+                    client: to,
+                    tick: self.tick,
+                    uuid,
+                    acceptors,
+                });
+                let committed = self
+                    .commit_log
+                    .iter()
+                    .filter(|commit| commit.client == to)
+                    .count() as u64;
+                if committed < self.allocations {
+                    for (next, message) in client.generate_requests() {
+                        self.network.send(to, next, message);
+                    }
+                }
+            }
+        }
+
+        // chaos
+        self.network.shuffle();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every client's id, sorted; panics if any repeats
+    fn unique_ids(cluster: &Cluster) -> Vec<Id> {
+        let mut ids: Vec<Id> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| commit.id)
+            .collect();
+        ids.sort_unstable();
+        let n = ids.len();
+        ids.dedup();
+        assert_eq!(ids.len(), n, "an id was committed twice");
+        ids
+    }
+
+    // five servers and six clients with the first `strict` servers on
+    // StrictNext and the rest on GreaterThan
+    fn mixed_cluster(strict: usize, seed: u64) -> Cluster {
+        let mut cluster = Cluster::new(5, 6).unwrap();
+        cluster.seed(seed);
+        for index in 0..strict {
+            cluster
+                .server_mut(index)
+                .unwrap()
+                .set_policy(crate::StrictNext);
+        }
+        cluster
+    }
+
+    // strict-next servers refuse to skip ids, greater-than servers don't
+    // mind. while the GreaterThan servers alone make up a quorum, a client
+    // lagging behind learns the max from whichever rejects it, so every id
+    // is still committed, uniquely, at the cost of some extra rounds.
+    #[test]
+    fn strict_next_minority_still_commits_unique_ids() {
+        for seed in 0..20 {
+            let mut cluster = mixed_cluster(2, seed);
+            cluster.run().unwrap();
+            assert_eq!(unique_ids(&cluster).len(), 6);
+        }
+    }
+
+    // with a StrictNext majority, a quorum needs StrictNext servers to agree
+    // on the next id. once their max ids diverge they never agree again (see
+    // StrictNext), and the clients retry forever without committing.
+    #[test]
+    fn strict_next_majority_can_livelock() {
+        let mut cluster = mixed_cluster(3, 54);
+        cluster.start();
+        for _ in 0..50_000 {
+            assert!(cluster.step().unwrap(), "seed 54 quiesced");
+        }
+        let stalled = cluster.commit_log().len();
+        assert!(stalled < 6);
+
+        for _ in 0..50_000 {
+            cluster.step().unwrap();
+        }
+        assert_eq!(cluster.commit_log().len(), stalled);
+        assert!(cluster.in_flight().count() > 0);
+
+        let mut strict: Vec<Id> = (0..3)
+            .map(|index| cluster.server(index).unwrap().max_id())
+            .collect();
+        strict.sort_unstable();
+        strict.dedup();
+        assert!(strict.len() > 1, "{:?}", strict);
+    }
+
+    // the version being rolled out: gaps are fine, unlike StrictNext, but
+    // not ones beyond MAX_GAP, unlike GreaterThan
+    #[derive(Debug)]
+    struct GreaterThanV2;
+
+    impl GreaterThanV2 {
+        const MAX_GAP: Id = 1_000;
+    }
+
+    impl AcceptPolicy for GreaterThanV2 {
+        fn accept(
+            &mut self,
+            _from: From,
+            _uuid: Uuid,
+            id: Id,
+            current_max: Id,
+        ) -> crate::AcceptDecision {
+            if id > current_max && id - current_max <= GreaterThanV2::MAX_GAP {
+                crate::AcceptDecision::Accept
+            } else {
+                crate::AcceptDecision::Reject
+            }
+        }
+    }
+
+    // whether a server takes an id `gap` above its max_id, proposed with a
+    // ballot nothing can be promised above
+    fn accepts(server: &mut Server, gap: Id) -> bool {
+        let id = server.max_id() + gap;
+        match server
+            .propose(0, Uuid::new_v4(), (u64::MAX, 0), id)
+            .unwrap()[0]
+            .1
+        {
+            Message::Response { success, .. } => success,
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    // mid-migration from StrictNext: servers 3 and 4 still refuse gaps, and
+    // being upgraded last they keep the cluster mixed until the final swap.
+    // two clients allocate back to back the whole time, and every soak
+    // between two swaps commits something.
+    #[test]
+    fn rolling_upgrade_keeps_serving() {
+        let soak: u64 = 200;
+        for seed in 0..5 {
+            let mut cluster = Cluster::new(5, 2).unwrap();
+            cluster.seed(seed);
+            cluster.allocations(50);
+            for index in 3..5 {
+                cluster
+                    .server_mut(index)
+                    .unwrap()
+                    .set_policy(crate::StrictNext);
+            }
+            cluster
+                .rolling_upgrade(|| GreaterThanV2, soak as usize)
+                .unwrap();
+            assert_eq!(unique_ids(&cluster).len(), 100);
+            cluster.check_against_reference().unwrap();
+
+            // server k is swapped at tick k * soak
+            for swap in 0..4 {
+                let (from, to) = (swap * soak, (swap + 1) * soak);
+                assert!(
+                    cluster
+                        .commit_log()
+                        .iter()
+                        .any(|commit| (from..to).contains(&commit.tick)),
+                    "seed {}: nothing committed in ticks {}..{}",
+                    seed,
+                    from,
+                    to
+                );
+            }
+
+            // every server ended on the new version, whatever it started on
+            for index in 0..5 {
+                let server = cluster.server_mut(index).unwrap();
+                assert!(!accepts(server, GreaterThanV2::MAX_GAP + 1));
+                assert!(accepts(server, 2));
+            }
+        }
+    }
+
+    #[test]
+    fn clients_allocate_back_to_back() {
+        for seed in 0..5 {
+            let mut cluster = Cluster::new(3, 3).unwrap();
+            cluster.seed(seed);
+            cluster.allocations(4);
+            cluster.run().unwrap();
+
+            assert_eq!(cluster.assert_all_idle(), Ok(()));
+            assert_eq!(unique_ids(&cluster).len(), 12);
+            for client in 3..6 {
+                let commits: Vec<&Commit> = cluster
+                    .commit_log()
+                    .iter()
+                    .filter(|commit| commit.client == client)
+                    .collect();
+                assert_eq!(commits.len(), 4);
+                // one allocation after another, each in a round of its own
+                assert!(commits.windows(2).all(|pair| pair[0].id < pair[1].id));
+                assert!(commits.windows(2).all(|pair| pair[0].uuid != pair[1].uuid));
+            }
+        }
+
+        // zero still allocates once
+        let mut cluster = Cluster::new(3, 2).unwrap();
+        cluster.seed(1);
+        cluster.allocations(0);
+        cluster.run().unwrap();
+        assert_eq!(unique_ids(&cluster).len(), 2);
+    }
+
+    #[test]
+    fn zero_clients_quiesce_at_once() {
+        let mut cluster = Cluster::new(3, 0).unwrap();
+        cluster.run().unwrap();
+        assert_eq!(cluster.now(), 0);
+        assert!(cluster.commit_log().is_empty());
+        assert_eq!(cluster.in_flight().count(), 0);
+        assert_eq!(cluster.assert_all_idle(), Ok(()));
+    }
+
+    #[test]
+    fn server_count_is_validated() {
+        assert_eq!(Cluster::new(0, 3).err(), Some(ConfigError::NoServers));
+        assert_eq!(Cluster::new(0, 0).err(), Some(ConfigError::NoServers));
+        assert_eq!(
+            Cluster::new_at(0, 5, 10).err(),
+            Some(ConfigError::NoServers)
+        );
+
+        // one server is a plain counter; two work but tolerate no failure
+        for (n_servers, tolerated) in [(1, 0), (2, 0), (3, 1), (4, 1), (5, 2)] {
+            let mut cluster = Cluster::new(n_servers, 4).unwrap();
+            assert_eq!(cluster.fault_tolerance(), tolerated);
+            cluster.seed(1);
+            cluster.run().unwrap();
+            assert_eq!(unique_ids(&cluster).len(), 4);
+        }
+    }
+
+    #[test]
+    fn commits_name_the_client_that_reached_quorum() {
+        let mut cluster = Cluster::new(5, 6).unwrap();
+        cluster.seed(4);
+        let committed = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        for index in 5..11 {
+            let committed = committed.clone();
+            let client = cluster.client_mut(index).unwrap();
+            client.on_commit(move |id| committed.lock().unwrap().push((id, index)));
+        }
+        cluster.run().unwrap();
+
+        let log: Vec<(Id, usize)> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| (commit.id, commit.client))
+            .collect();
+        assert_eq!(log, *committed.lock().unwrap());
+
+        let mut uuids: Vec<Uuid> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| commit.uuid)
+            .collect();
+        uuids.sort_unstable();
+        uuids.dedup();
+        assert_eq!(uuids.len(), 6);
+    }
+
+    #[test]
+    fn acceptors_are_a_distinct_majority() {
+        for n_servers in [3, 4, 5] {
+            for seed in 0..5 {
+                let mut cluster = Cluster::new(n_servers, 6).unwrap();
+                cluster.seed(seed);
+                cluster.run().unwrap();
+                assert_eq!(cluster.commit_log().len(), 6);
+
+                for commit in cluster.commit_log() {
+                    let mut acceptors = commit.acceptors.clone();
+                    acceptors.sort_unstable();
+                    acceptors.dedup();
+                    assert_eq!(acceptors.len(), commit.acceptors.len());
+                    assert!(acceptors.len() > n_servers / 2);
+                    assert!(acceptors.iter().all(|server| *server < n_servers));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn csv_export_parses_back_into_the_commit_log() {
+        let mut cluster = Cluster::new(5, 6).unwrap();
+        cluster.seed(8);
+        cluster.run().unwrap();
+
+        let mut csv = vec![];
+        cluster.export_commits(&mut csv, ExportFormat::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("id,client,tick,uuid,acceptors"));
+
+        let parsed: Vec<Commit> = lines
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').collect();
+                assert_eq!(fields.len(), 5, "{}", line);
+                Commit {
+                    id: fields[0].parse().unwrap(),
+                    client: fields[1].parse().unwrap(),
+                    tick: fields[2].parse().unwrap(),
+                    uuid: Uuid::parse_str(fields[3]).unwrap(),
+                    acceptors: fields[4].split(';').map(|a| a.parse().unwrap()).collect(),
+                }
+            })
+            .collect();
+        assert_eq!(parsed.len(), 6);
+        assert_eq!(parsed, cluster.commit_log());
+
+        let mut json = vec![];
+        cluster
+            .export_commits(&mut json, ExportFormat::JsonLines)
+            .unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert_eq!(json.lines().count(), 6);
+        let first = &cluster.commit_log()[0];
+        assert!(json.lines().next().unwrap().starts_with(&format!(
+            "{{\"id\":{},\"client\":{},",
+            first.id, first.client
+        )));
+    }
+
+    #[test]
+    fn abandoning_stranded_rounds_retracts_their_speculation() {
+        let mut cluster = Cluster::new(3, 1).unwrap();
+        let retracted = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let log = retracted.clone();
+        cluster
+            .client_mut(3)
+            .unwrap()
+            .speculate(move |speculation| log.lock().unwrap().push(speculation));
+        cluster.start();
+
+        // one accept gets through, the rest of the round is lost
+        let (first, ..) = cluster.in_flight().next().unwrap();
+        let rest: Vec<MsgSeq> = cluster
+            .in_flight()
+            .map(|(seq, ..)| seq)
+            .filter(|seq| *seq != first)
+            .collect();
+        for seq in &rest {
+            cluster.hold(*seq);
+        }
+        cluster.drain().unwrap();
+        assert_eq!(
+            *retracted.lock().unwrap(),
+            vec![crate::Speculation::Issued(1)]
+        );
+
+        assert_eq!(cluster.abandon_stranded().len(), 1);
+        assert_eq!(cluster.assert_all_idle(), Ok(()));
+        assert_eq!(
+            *retracted.lock().unwrap(),
+            vec![
+                crate::Speculation::Issued(1),
+                crate::Speculation::Retracted(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn msg_seqs_are_unique_and_increasing() {
+        let mut cluster = Cluster::new(3, 4).unwrap();
+        cluster.seed(2);
+        cluster.start();
+
+        let mut seen: Vec<MsgSeq> = vec![];
+        loop {
+            // anything not seen before was enqueued after everything seen
+            let mut new: Vec<MsgSeq> = cluster
+                .in_flight()
+                .map(|(seq, ..)| seq)
+                .filter(|seq| !seen.contains(seq))
+                .collect();
+            new.sort_unstable();
+            if let (Some(last), Some(first)) = (seen.last(), new.first()) {
+                assert!(first > last);
+            }
+            seen.extend(new);
+            if !cluster.step().unwrap() {
+                break;
+            }
+        }
+
+        assert_eq!(seen, (0..seen.len() as MsgSeq).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn warm_start_allocates_above_the_checkpoint() {
+        let mut cluster = Cluster::new_at(3, 3, 1_000_000).unwrap();
+        cluster.seed(5);
+        cluster.run().unwrap();
+        let ids = unique_ids(&cluster);
+        assert_eq!(ids.len(), 3);
+        assert!(ids[0] > 1_000_000);
+    }
+
+    #[test]
+    fn clock_only_moves_forward() {
+        let mut cluster = Cluster::new(3, 4).unwrap();
+        cluster.seed(6);
+        cluster.start();
+        while cluster.commit_log().is_empty() {
+            cluster.step().unwrap();
+        }
+
+        assert_eq!(cluster.set_now(1_000), Ok(()));
+        assert_eq!(cluster.now(), 1_000);
+        assert_eq!(
+            cluster.set_now(10),
+            Err(ClockRewind {
+                now: 1_000,
+                attempted: 10
+            })
+        );
+        assert_eq!(cluster.now(), 1_000);
+        assert_eq!(cluster.set_now(1_000), Ok(()));
+        cluster.drain().unwrap();
+
+        let ticks: Vec<u64> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| commit.tick)
+            .collect();
+        assert!(ticks[1..].iter().all(|tick| *tick > 1_000));
+        assert!(ticks.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    // the order of seqs after one shuffle of `len` messages
+    fn permutation(network: &mut Network, len: usize, uuid: Uuid) -> Vec<MsgSeq> {
+        network.in_flight.clear();
+        network.next_seq = 0;
+        for to in 0..len {
+            network.send(0, to, Message::Decommissioning { uuid });
+        }
+        network.shuffle();
+        network.in_flight.iter().map(|(seq, ..)| *seq).collect()
+    }
+
+    #[test]
+    fn shuffle_repeats_from_the_same_rng_state() {
+        let mut network = Network::new(StdRng::seed_from_u64(0), StdRng::seed_from_u64(9));
+        let snapshot = network.scheduling.clone();
+        let first = permutation(&mut network, 16, Uuid::nil());
+
+        network.scheduling = snapshot.clone();
+        // different payloads, same length: the same permutation
+        assert_eq!(permutation(&mut network, 16, Uuid::new_v4()), first);
+
+        network.scheduling = snapshot;
+        permutation(&mut network, 16, Uuid::nil());
+        assert_ne!(permutation(&mut network, 16, Uuid::nil()), first);
+        assert_ne!(first, (0..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn messages_to_missing_nodes_are_dropped() {
+        let mut cluster = Cluster::new(3, 1).unwrap();
+        // as if server 7 had been removed with a request still on its way
+        let uuid = Uuid::new_v4();
+        cluster.network.send(
+            3,
+            7,
+            Message::Request {
+                uuid,
+                ballot: (1, 3),
+                id: 1,
+            },
+        );
+        cluster.run().unwrap();
+
+        assert_eq!(cluster.stale_target_dropped(), 1);
+        assert_eq!(unique_ids(&cluster), vec![1]);
+    }
+
+    #[test]
+    fn two_phase_runs_leave_no_pending_prepares() {
+        for seed in 0..20 {
+            let mut cluster = Cluster::new(5, 6).unwrap();
+            cluster.seed(seed);
+            for index in 5..11 {
+                cluster.client_mut(index).unwrap().two_phase(true);
+            }
+            cluster.run().unwrap();
+            for index in 0..5 {
+                assert_eq!(cluster.server(index).unwrap().pending(), &[]);
+            }
+        }
+    }
+
+    // total deliveries to quiesce over a run of seeds, every client in the
+    // given mode
+    fn deliveries(two_phase: bool, decommission: bool) -> u64 {
+        (0..5)
+            .map(|seed| {
+                let mut cluster = Cluster::new(10, 15).unwrap();
+                cluster.seed(seed);
+                for index in 10..25 {
+                    cluster.client_mut(index).unwrap().two_phase(two_phase);
+                }
+                if decommission {
+                    cluster.server_mut(9).unwrap().begin_decommission();
+                }
+                cluster.run().unwrap();
+                assert_eq!(unique_ids(&cluster).len(), 15);
+                cluster.now()
+            })
+            .sum()
+    }
+
+    // re-preparing after every preemption used to take ~27x the deliveries
+    // of one-shot here, and ~90x with a server retiring
+    #[test]
+    fn two_phase_costs_no_more_than_one_shot_under_random_scheduling() {
+        for decommission in [false, true] {
+            let one_shot = deliveries(false, decommission);
+            let two_phase = deliveries(true, decommission);
+            assert!(
+                two_phase < one_shot * 5 / 4,
+                "{} vs {}",
+                two_phase,
+                one_shot
+            );
+        }
+    }
+
+    // A's proposal only reaches server 0 while B's reaches everyone. B
+    // commits the id A still hopes for; when A's held proposals finally
+    // arrive they are rejected, and A moves on to the next id instead of
+    // committing a duplicate.
+    #[test]
+    fn held_proposal_loses_the_race_safely() {
+        let mut cluster = Cluster::new(3, 2).unwrap();
+        cluster.seed(3);
+        cluster.start();
+        let held: Vec<MsgSeq> = cluster
+            .in_flight()
+            .filter(|(_, from, to, _)| *from == 3 && *to != 0)
+            .map(|(seq, ..)| seq)
+            .collect();
+        assert_eq!(held.len(), 2);
+        for seq in &held {
+            cluster.hold(*seq);
+        }
+
+        cluster.drain().unwrap();
+        let commits: Vec<(Id, usize)> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| (commit.id, commit.client))
+            .collect();
+        assert_eq!(commits, vec![(1, 4)]);
+        assert_eq!(cluster.in_flight().count(), 2);
+
+        for seq in &held {
+            cluster.release(*seq);
+        }
+        cluster.drain().unwrap();
+        let commits: Vec<(Id, usize)> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| (commit.id, commit.client))
+            .collect();
+        assert_eq!(commits, vec![(1, 4), (2, 3)]);
+        assert_eq!(cluster.assert_all_idle(), Ok(()));
+    }
+
+    #[test]
+    fn loss_ratio_is_validated_and_off_by_default() {
+        let mut cluster = Cluster::new(3, 3).unwrap();
+        assert_eq!(cluster.loss(1, 0), Err(ConfigError::LossRatio));
+        assert_eq!(cluster.loss(2, 1), Err(ConfigError::LossRatio));
+        cluster.seed(1);
+        cluster.run().unwrap();
+        assert_eq!(cluster.lost(), 0);
+        assert_eq!(cluster.rng_usage().loss, 0);
+    }
+
+    // a loss ratio too small to ever drop anything still draws once per
+    // message, yet the same seed schedules and names every round the same
+    #[test]
+    fn loss_draws_leave_the_other_streams_alone() {
+        for seed in 0..5 {
+            let mut plain = Cluster::new(5, 6).unwrap();
+            plain.seed(seed);
+            plain.run().unwrap();
+
+            let mut lossy = Cluster::new(5, 6).unwrap();
+            lossy.seed(seed);
+            lossy.loss(1, u32::MAX).unwrap();
+            lossy.run().unwrap();
+
+            assert_eq!(lossy.lost(), 0);
+            assert_eq!(plain.rng_usage().loss, 0);
+            assert!(lossy.rng_usage().loss > 0);
+            assert_eq!(
+                RngUsage {
+                    loss: 0,
+                    ..lossy.rng_usage()
+                },
+                plain.rng_usage()
+            );
+            assert_eq!(lossy.commit_log(), plain.commit_log());
+        }
+    }
+
+    // one client allocating back to back over a fault-free network, in
+    // lockstep with the reference: the very same ids, in the same order
+    #[test]
+    fn serial_runs_match_the_reference_exactly() {
+        for n_servers in [1, 3, 4, 5] {
+            for seed in 0..5 {
+                let mut cluster = Cluster::new_at(n_servers, 1, 100).unwrap();
+                cluster.seed(seed);
+                cluster.allocations(20);
+                cluster.run().unwrap();
+                cluster.check_against_reference().unwrap();
+
+                let ids: Vec<Id> = cluster
+                    .commit_log()
+                    .iter()
+                    .map(|commit| commit.id)
+                    .collect();
+                assert_eq!(ids, (101..=120).collect::<Vec<Id>>());
+            }
+        }
+    }
+
+    #[test]
+    fn runs_agree_with_the_reference() {
+        for seed in 0..10 {
+            for lossy in [false, true] {
+                let mut cluster = Cluster::new(5, 6).unwrap();
+                cluster.seed(seed);
+                if lossy {
+                    cluster.loss(3, 10).unwrap();
+                }
+                cluster.run().unwrap();
+                cluster.check_against_reference().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn reference_check_catches_a_reordered_serial_run() {
+        let mut cluster = Cluster::new(3, 1).unwrap();
+        cluster.seed(1);
+        cluster.allocations(3);
+        cluster.run().unwrap();
+        cluster.check_against_reference().unwrap();
+
+        // still distinct and accepted by the servers, just not what the
+        // reference would have handed out
+        cluster.commit_log.swap(0, 1);
+        assert!(matches!(
+            cluster.check_against_reference(),
+            Err(Violation::Invariant(_))
+        ));
+    }
+
+    #[test]
+    fn reference_check_catches_a_duplicate_and_an_unaccepted_id() {
+        let mut cluster = Cluster::new(3, 2).unwrap();
+        cluster.seed(1);
+        cluster.run().unwrap();
+        cluster.check_against_reference().unwrap();
+
+        let original = cluster.commit_log[1].clone();
+        cluster.commit_log[1].id = cluster.commit_log[0].id;
+        assert!(matches!(
+            cluster.check_against_reference(),
+            Err(Violation::Invariant(_))
+        ));
+
+        // no server ever moved this far
+        cluster.commit_log[1] = Commit {
+            id: cluster.server(0).unwrap().max_id() + 100,
+            ..original
+        };
+        assert!(matches!(
+            cluster.check_against_reference(),
+            Err(Violation::Invariant(_))
+        ));
+    }
+
+    // recorded from this seed and workload; a change here means the same
+    // seed no longer replays the same run
+    #[test]
+    fn rng_usage_matches_the_recorded_counts() {
+        let mut cluster = Cluster::new(5, 6).unwrap();
+        cluster.seed(42);
+        cluster.loss(1, 10).unwrap();
+        cluster.run().unwrap();
+        assert_eq!(
+            cluster.rng_usage(),
+            RngUsage {
+                loss: 134,
+                scheduling: 1941,
+                uuid: 14,
+            }
+        );
+    }
+
+    #[test]
+    fn heavy_loss_wastes_ids_but_keeps_them_unique() {
+        let (mut wasted, mut wasted_lossy) = (0, 0);
+        for seed in 0..20 {
+            let mut cluster = Cluster::new(5, 6).unwrap();
+            cluster.seed(seed);
+            cluster.run().unwrap();
+            unique_ids(&cluster);
+            wasted += cluster.wasted_ids();
+
+            let mut cluster = Cluster::new(5, 6).unwrap();
+            cluster.seed(seed);
+            cluster.loss(3, 10).unwrap();
+            cluster.run().unwrap();
+            assert!(cluster.lost() > 0);
+            unique_ids(&cluster);
+            wasted_lossy += cluster.wasted_ids();
+        }
+        assert!(wasted_lossy > wasted, "{} vs {}", wasted_lossy, wasted);
+    }
+
+    // a round whose messages are all lost is left pending when the run
+    // quiesces, and is reported rather than mistaken for a finished client
+    #[test]
+    fn lost_rounds_are_reported_stranded() {
+        let mut stranded_runs = 0;
+        for seed in 0..10 {
+            let mut cluster = Cluster::new(3, 4).unwrap();
+            cluster.seed(seed);
+            cluster.loss(1, 2).unwrap();
+            cluster.run().unwrap();
+
+            let stranded = match cluster.assert_all_idle() {
+                Ok(()) => continue,
+                Err(stranded) => stranded,
+            };
+            stranded_runs += 1;
+            for Stranded { client, uuid, id } in stranded {
+                assert!(cluster.commit_log().iter().all(|c| c.client != client));
+                let client = cluster.client_mut(client).unwrap();
+                assert_eq!(client.pending_round(), Some((uuid, id)));
+            }
+        }
+        assert!(stranded_runs > 0);
+    }
+
+    #[test]
+    fn even_server_count_strands_nobody() {
+        for seed in 0..10 {
+            let mut cluster = Cluster::new(10, 15).unwrap();
+            cluster.seed(seed);
+            cluster.run().unwrap();
+            assert_eq!(cluster.assert_all_idle(), Ok(()));
+            assert_eq!(unique_ids(&cluster).len(), 15);
+        }
+    }
+
+    #[test]
+    fn decommissioned_server_is_routed_around() {
+        for seed in 0..20 {
+            let mut cluster = Cluster::new(5, 6).unwrap();
+            cluster.seed(seed);
+            cluster.server_mut(4).unwrap().begin_decommission();
+            cluster.run().unwrap();
+
+            assert_eq!(unique_ids(&cluster).len(), 6);
+            assert_eq!(cluster.server(4).unwrap().max_id(), 0);
+        }
+    }
+}
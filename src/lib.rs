@@ -0,0 +1,1641 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use uuid::{Builder, Uuid, Variant, Version};
+
+// checked in every build, unlike assert!; fails the enclosing function
+// with a Violation instead of panicking
+macro_rules! verify {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err(crate::Violation::Invariant(format!($($arg)+)));
+        }
+    };
+}
+
+mod builder;
+pub mod channel;
+mod cluster;
+mod reference;
+mod sink;
+pub mod transport;
+
+pub use builder::{ClientBuilder, ServerBuilder};
+pub use cluster::{
+    ClockRewind, Cluster, Commit, ConfigError, ExportFormat, MsgSeq, RngStream, RngUsage, Stranded,
+};
+pub use reference::Reference;
+pub use sink::{ChannelSink, FileSink, IdSink};
+
+pub type Id = u64;
+pub type Success = bool;
+// (round, proposing client), compared lexicographically
+pub type Ballot = (u64, usize);
+pub type To = usize;
+pub type From = usize;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    // request ID, proposal ballot and proposed ID
+    Request {
+        uuid: Uuid,
+        ballot: Ballot,
+        id: Id,
+    },
+
+    // proposal accepted?, request ID, server's highest promised ballot, server's highest known ID
+    Response {
+        success: Success,
+        uuid: Uuid,
+        ballot: Ballot,
+        id: Id,
+    },
+
+    // several Responses from one server in one frame, each handled as if it
+    // arrived on its own, in order
+    ResponseBatch {
+        responses: Vec<Response>,
+    },
+
+    // request ID; the server is being retired and no longer accepts proposals
+    Decommissioning {
+        uuid: Uuid,
+    },
+
+    // two-phase only: request ID and the ballot to reserve before proposing
+    Prepare {
+        uuid: Uuid,
+        ballot: Ballot,
+    },
+
+    // ballot promised?, request ID, server's highest promised ballot, server's highest known ID
+    Promise {
+        success: Success,
+        uuid: Uuid,
+        ballot: Ballot,
+        id: Id,
+    },
+}
+
+// the fields of one Message::Response, for carrying several in a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response {
+    pub success: Success,
+    pub uuid: Uuid,
+    pub ballot: Ballot,
+    pub id: Id,
+}
+
+// how many servers must accept an id before the client treats it as committed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    // strictly more than half
+    #[default]
+    Quorum,
+    // every server; one that never answers blocks the commit
+    All,
+}
+
+impl Durability {
+    fn accepts_needed(self, n_servers: usize) -> usize {
+        match self {
+            Durability::Quorum => n_servers / 2 + 1,
+            Durability::All => n_servers,
+        }
+    }
+
+    // rejections after which the round is abandoned and retried: one more
+    // and the accepts needed are out of reach. with an even count, n/2
+    // would let an exact split wait forever.
+    fn rejects_tolerated(self, n_servers: usize) -> usize {
+        n_servers - self.accepts_needed(n_servers)
+    }
+}
+
+// what a speculating client reports about an id it handed out early.
+// an Issued id is NOT unique until it is Confirmed: the round may still fail
+// and leave the same id free for another client to commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speculation {
+    // the first server accepted it
+    Issued(Id),
+    // the round reached quorum
+    Confirmed(Id),
+    // the round was abandoned; the id must not be used
+    Retracted(Id),
+}
+
+#[derive(Debug)]
+pub enum Computer {
+    Server(Server),
+    Client(Client),
+}
+
+impl Computer {
+    pub fn receive(
+        &mut self,
+        from: From,
+        message: Message,
+    ) -> Result<Vec<(To, Message)>, Violation> {
+        match (self, message) {
+            (Computer::Server(server), Message::Request { uuid, ballot, id }) => {
+                server.propose(from, uuid, ballot, id)
+            }
+            (Computer::Server(server), Message::Prepare { uuid, ballot }) => {
+                Ok(server.prepare(from, uuid, ballot))
+            }
+            (
+                Computer::Client(client),
+                Message::Promise {
+                    success,
+                    uuid,
+                    ballot,
+                    id,
+                },
+            ) => Ok(client.receive_promise(from, success, uuid, ballot, id)),
+            (
+                Computer::Client(client),
+                Message::Response {
+                    success,
+                    uuid,
+                    ballot,
+                    id,
+                },
+            ) => client.receive(from, success, uuid, ballot, id),
+            (Computer::Client(client), Message::ResponseBatch { responses }) => {
+                client.receive_batch(from, responses)
+            }
+            (Computer::Client(client), Message::Decommissioning { uuid }) => {
+                Ok(client.route_around(from, uuid))
+            }
+            (_, message) => Err(Violation::Invariant(format!(
+                "{:?} from {} delivered to the wrong kind of computer",
+                message, from
+            ))),
+        }
+    }
+}
+
+// an attempt to move a server's max_id backward
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SafetyViolation {
+    pub max_id: Id,
+    pub attempted: Id,
+}
+
+// a protocol invariant that did not hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    Safety(SafetyViolation),
+    Invariant(String),
+}
+
+impl std::convert::From<SafetyViolation> for Violation {
+    fn from(violation: SafetyViolation) -> Violation {
+        Violation::Safety(violation)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptDecision {
+    Accept,
+    Reject,
+}
+
+// decides whether a server takes a proposed id. the server never accepts an
+// id at or below its max_id regardless, so a policy can only be stricter.
+pub trait AcceptPolicy: std::fmt::Debug + Send {
+    fn accept(&mut self, from: From, uuid: Uuid, id: Id, current_max: Id) -> AcceptDecision;
+}
+
+// anything above the current max
+#[derive(Debug, Default)]
+pub struct GreaterThan;
+
+impl AcceptPolicy for GreaterThan {
+    fn accept(&mut self, _from: From, _uuid: Uuid, id: Id, current_max: Id) -> AcceptDecision {
+        if id > current_max {
+            AcceptDecision::Accept
+        } else {
+            AcceptDecision::Reject
+        }
+    }
+}
+
+// only the id directly after the current max, so no gaps on this server
+// a server that falls behind never catches up, so once the max ids of a
+// StrictNext majority diverge no id can win a quorum again
+#[derive(Debug, Default)]
+pub struct StrictNext;
+
+impl AcceptPolicy for StrictNext {
+    fn accept(&mut self, _from: From, _uuid: Uuid, id: Id, current_max: Id) -> AcceptDecision {
+        if id == current_max + 1 {
+            AcceptDecision::Accept
+        } else {
+            AcceptDecision::Reject
+        }
+    }
+}
+
+// a granted prepare whose proposal hasn't arrived yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingRequest {
+    pub from: From,
+    pub uuid: Uuid,
+    pub ballot: Ballot,
+}
+
+#[derive(Debug)]
+pub struct Server {
+    max_id: u64,
+
+    policy: Box<dyn AcceptPolicy>,
+
+    // highest ballot seen; proposals below it are rejected
+    promised: Ballot,
+    // highest ballot a proposal arrived with; a prepare for it that was
+    // overtaken by its own proposal has nothing left to wait for
+    proposed: Ballot,
+
+    // being retired: refuses new proposals but keeps its state
+    decommissioning: bool,
+
+    // freed when the proposal arrives, a higher ballot supersedes it, or its
+    // client moves on to another round, so this holds at most one round per
+    // client and only at the promised ballot
+    pending: Vec<PendingRequest>,
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::with_policy(GreaterThan)
+    }
+}
+
+impl Server {
+    pub fn with_policy(policy: impl AcceptPolicy + 'static) -> Server {
+        Server {
+            max_id: 0,
+            policy: Box::new(policy),
+            promised: Ballot::default(),
+            proposed: Ballot::default(),
+            decommissioning: false,
+            pending: vec![],
+        }
+    }
+
+    // continue an existing id space: nothing at or below max_id is handed out
+    pub fn new_at(max_id: Id) -> Server {
+        Server {
+            max_id,
+            ..Server::default()
+        }
+    }
+
+    pub fn max_id(&self) -> Id {
+        self.max_id
+    }
+
+    pub fn policy(&self) -> &dyn AcceptPolicy {
+        &*self.policy
+    }
+
+    // an upgrade in place: max_id and promises carry over, only the rule
+    // for future proposals changes
+    pub fn set_policy(&mut self, policy: impl AcceptPolicy + 'static) {
+        self.policy = Box::new(policy);
+    }
+
+    pub fn pending(&self) -> &[PendingRequest] {
+        &self.pending
+    }
+
+    // prepares granted before this can never be followed by an accept, so
+    // they are freed now rather than held forever
+    pub fn begin_decommission(&mut self) {
+        self.decommissioning = true;
+        self.pending.clear();
+    }
+
+    // reinstate a previously saved high-water mark
+    pub fn restore(&mut self, max_id: Id) -> Result<(), SafetyViolation> {
+        self.set_max_id(max_id)
+    }
+
+    // every write to max_id must go through here
+    fn set_max_id(&mut self, id: Id) -> Result<(), SafetyViolation> {
+        if id < self.max_id {
+            println!(
+                "SAFETY VIOLATION; MAX_ID = {}, ATTEMPTED = {}",
+                self.max_id, id
+            );
+            return Err(SafetyViolation {
+                max_id: self.max_id,
+                attempted: id,
+            });
+        }
+        self.max_id = id;
+        Ok(())
+    }
+
+    // phase 1: reserve the ballot and report the highest id we've accepted
+    fn prepare(&mut self, from: From, uuid: Uuid, ballot: Ballot) -> Vec<(To, Message)> {
+        if self.decommissioning {
+            return vec![(from, Message::Decommissioning { uuid })];
+        }
+
+        // a client runs one round at a time, so its earlier ones are dead
+        self.pending.retain(|pending| pending.from != from);
+
+        let success = ballot >= self.promised;
+        if success {
+            self.promise(ballot);
+            if ballot != self.proposed {
+                self.pending.push(PendingRequest { from, uuid, ballot });
+            }
+        }
+        vec![(
+            from,
+            Message::Promise {
+                success,
+                uuid,
+                ballot: self.promised,
+                id: self.max_id,
+            },
+        )]
+    }
+
+    fn propose(
+        &mut self,
+        from: From,
+        uuid: Uuid,
+        ballot: Ballot,
+        id: Id,
+    ) -> Result<Vec<(To, Message)>, Violation> {
+        if self.decommissioning {
+            return Ok(vec![(from, Message::Decommissioning { uuid })]);
+        }
+        self.pending.retain(|pending| pending.from != from);
+
+        if ballot < self.promised {
+            return Ok(vec![(from, self.reject(uuid))]);
+        }
+        self.promise(ballot);
+        self.proposed = ballot;
+
+        let decision = self.policy.accept(from, uuid, id, self.max_id);
+        if decision == AcceptDecision::Accept && id > self.max_id {
+            self.set_max_id(id)?;
+            return Ok(vec![(
+                from,
+                Message::Response {
+                    success: true,
+                    uuid,
+                    ballot,
+                    id,
+                },
+            )]);
+        }
+        Ok(vec![(from, self.reject(uuid))])
+    }
+
+    fn promise(&mut self, ballot: Ballot) {
+        self.promised = ballot;
+        self.pending.retain(|pending| pending.ballot >= ballot);
+    }
+
+    fn reject(&self, uuid: Uuid) -> Message {
+        Message::Response {
+            success: false,
+            uuid,
+            ballot: self.promised,
+            id: self.max_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientError {
+    // max_retries rounds in a row failed for one allocation, or so many
+    // servers are being retired that the durability can't be met at all
+    QuorumUnreachable,
+    // the caller dropped the open round with abandon
+    Abandoned,
+}
+
+pub struct Client {
+    last_id: Id,
+
+    // the servers are indices 0..n_servers
+    n_servers: usize,
+
+    // ballot of the current round, bumped past any higher one we hear about
+    ballot: Ballot,
+
+    // in-flight request ID
+    current_uuid: Uuid,
+    current_responses: Vec<(From, Result<Id, Id>)>,
+    current_durability: Durability,
+    // the committed id must be above this
+    current_floor: Id,
+
+    // opt-in: reserve a ballot with a majority before proposing, and propose
+    // above the highest id they report
+    two_phase: bool,
+    preparing: bool,
+    // a prepare of this allocation lost to a higher ballot
+    preempted: bool,
+    current_promises: Vec<(From, Result<Id, Id>)>,
+
+    // servers that told us they are being retired; left out of new rounds
+    decommissioning: Vec<To>,
+
+    // called once for every id this client commits
+    on_commit: Option<Box<dyn FnMut(Id) + Send>>,
+
+    // id, request ID and accepting servers of the latest commit, until the
+    // cluster records it
+    unrecorded_commit: Option<(Id, Uuid, Vec<From>)>,
+
+    // opt-in: hand ids out on the first accept instead of waiting for quorum
+    on_speculate: Option<Box<dyn FnMut(Speculation) + Send>>,
+    speculated: Option<Id>,
+    speculation_hits: u64,
+    speculation_misses: u64,
+
+    // failed rounds for the current allocation, and how many we put up with
+    retries: u32,
+    max_retries: Option<u32>,
+    error: Option<ClientError>,
+
+    // highest id any client sharing this has committed; only a starting
+    // point, the quorum still decides
+    hint: Option<Arc<AtomicU64>>,
+
+    // round uuids are drawn from here when set, so seeded runs repeat them;
+    // paired with how many have been drawn
+    uuid_rng: Option<Box<(StdRng, u64)>>,
+}
+
+impl std::fmt::Debug for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Client")
+            .field("last_id", &self.last_id)
+            .field("n_servers", &self.n_servers)
+            .field("ballot", &self.ballot)
+            .field("current_uuid", &self.current_uuid)
+            .field("current_responses", &self.current_responses)
+            .field("current_durability", &self.current_durability)
+            .field("current_floor", &self.current_floor)
+            .field("two_phase", &self.two_phase)
+            .field("preparing", &self.preparing)
+            .field("preempted", &self.preempted)
+            .field("current_promises", &self.current_promises)
+            .field("decommissioning", &self.decommissioning)
+            .field("on_commit", &self.on_commit.is_some())
+            .field("unrecorded_commit", &self.unrecorded_commit)
+            .field("on_speculate", &self.on_speculate.is_some())
+            .field("speculated", &self.speculated)
+            .field("speculation_hits", &self.speculation_hits)
+            .field("speculation_misses", &self.speculation_misses)
+            .field("retries", &self.retries)
+            .field("max_retries", &self.max_retries)
+            .field("error", &self.error)
+            .field("hint", &self.hint)
+            .field("uuid_rng", &self.uuid_rng)
+            .finish()
+    }
+}
+
+impl Client {
+    pub fn new(index: usize, n_servers: usize) -> Client {
+        Client {
+            last_id: 0,
+            n_servers,
+            ballot: (0, index),
+            current_uuid: Uuid::nil(),
+            current_responses: vec![],
+            current_durability: Durability::default(),
+            current_floor: 0,
+            two_phase: false,
+            preparing: false,
+            preempted: false,
+            current_promises: vec![],
+            decommissioning: vec![],
+            on_commit: None,
+            unrecorded_commit: None,
+            on_speculate: None,
+            speculated: None,
+            speculation_hits: 0,
+            speculation_misses: 0,
+            retries: 0,
+            max_retries: None,
+            error: None,
+            hint: None,
+            uuid_rng: None,
+        }
+    }
+
+    // prepare a ballot with a majority before proposing, and propose above
+    // the highest id they report. this breaks the even split that concurrent
+    // one-shot proposals at the same max_id can fall into, but it is no
+    // general cure: proposers re-preparing past each other's ballots preempt
+    // each other again and again. so once a prepare is preempted, the rest
+    // of the allocation falls back to one-shot rounds, and under the random
+    // scheduler both modes take about as many deliveries.
+    pub fn two_phase(&mut self, enabled: bool) {
+        self.two_phase = enabled;
+    }
+
+    // give up on an allocation instead of retrying it forever; None retries
+    // without limit
+    pub fn max_retries(&mut self, max_retries: Option<u32>) {
+        self.max_retries = max_retries;
+    }
+
+    // failed rounds so far for the current allocation
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    // for clients in one process: skip rounds that are already known to be
+    // too low by starting from the highest id any of them committed
+    pub fn share_hint(&mut self, hint: Arc<AtomicU64>) {
+        self.hint = Some(hint);
+    }
+
+    // the round still waiting on servers, and the id it proposes; None once
+    // the allocation committed or gave up
+    pub fn pending_round(&self) -> Option<(Uuid, Id)> {
+        if self.current_uuid.is_nil() {
+            None
+        } else {
+            Some((self.current_uuid, self.proposal()))
+        }
+    }
+
+    // random (v4) uuids for each round, but reproducible from `seed`
+    pub fn seed_uuids(&mut self, seed: u64) {
+        self.uuid_rng = Some(Box::new((StdRng::seed_from_u64(seed), 0)));
+    }
+
+    // uuids taken from the seeded stream so far
+    pub fn uuids_drawn(&self) -> u64 {
+        self.uuid_rng.as_ref().map_or(0, |stream| stream.1)
+    }
+
+    fn next_uuid(&mut self) -> Uuid {
+        match &mut self.uuid_rng {
+            Some(stream) => {
+                let (rng, drawn) = &mut **stream;
+                *drawn += 1;
+                let mut bytes = [0; 16];
+                rng.fill_bytes(&mut bytes);
+                Builder::from_bytes(bytes)
+                    .set_variant(Variant::RFC4122)
+                    .set_version(Version::Random)
+                    .build()
+            }
+            None => Uuid::new_v4(),
+        }
+    }
+
+    // why the last allocation was abandoned, if it was
+    pub fn error(&self) -> Option<ClientError> {
+        self.error
+    }
+
+    pub fn on_commit(&mut self, hook: impl FnMut(Id) + Send + 'static) {
+        self.on_commit = Some(Box::new(hook));
+    }
+
+    // enables speculation; see Speculation for why this is unsafe
+    pub fn speculate(&mut self, hook: impl FnMut(Speculation) + Send + 'static) {
+        self.on_speculate = Some(Box::new(hook));
+    }
+
+    // (confirmed, retracted) speculative ids so far
+    pub fn speculation_stats(&self) -> (u64, u64) {
+        (self.speculation_hits, self.speculation_misses)
+    }
+
+    fn resolve_speculation(&mut self, outcome: fn(Id) -> Speculation) {
+        if let (Some(id), Some(hook)) = (self.speculated.take(), &mut self.on_speculate) {
+            let outcome = outcome(id);
+            if let Speculation::Confirmed(_) = outcome {
+                self.speculation_hits += 1;
+            } else {
+                self.speculation_misses += 1;
+            }
+            hook(outcome);
+        }
+    }
+
+    pub fn generate_requests(&mut self) -> Vec<(To, Message)> {
+        self.allocate(Durability::default())
+    }
+
+    pub fn allocate(&mut self, durability: Durability) -> Vec<(To, Message)> {
+        self.begin(durability, 0)
+    }
+
+    // an id strictly greater than `floor`, e.g. to stay ahead of an external
+    // counter. if the cluster is already past it this is a normal allocation.
+    pub fn allocate_at_least(&mut self, floor: Id) -> Vec<(To, Message)> {
+        self.begin(Durability::default(), floor)
+    }
+
+    fn begin(&mut self, durability: Durability, floor: Id) -> Vec<(To, Message)> {
+        self.current_durability = durability;
+        self.current_floor = floor;
+        self.retries = 0;
+        self.preempted = false;
+        self.error = None;
+        self.start_round()
+    }
+
+    // start over on the current allocation, e.g. after a transport timeout
+    pub fn retry(&mut self) -> Vec<(To, Message)> {
+        self.retries += 1;
+        if self.max_retries.is_some_and(|max| self.retries > max) {
+            return self.give_up();
+        }
+        self.start_round()
+    }
+
+    // drop the open round without retrying, e.g. once nothing more will be
+    // delivered for it. a speculated id is retracted, and late answers are
+    // ignored, so it can never be confirmed afterwards.
+    pub fn abandon(&mut self) {
+        self.resolve_speculation(Speculation::Retracted);
+        self.current_uuid = Uuid::nil();
+        self.error = Some(ClientError::Abandoned);
+    }
+
+    fn give_up(&mut self) -> Vec<(To, Message)> {
+        self.resolve_speculation(Speculation::Retracted);
+        // ignore whatever is still in flight for the abandoned round
+        self.current_uuid = Uuid::nil();
+        self.error = Some(ClientError::QuorumUnreachable);
+        println!("GAVE UP; ID = {}", self.proposal());
+        vec![]
+    }
+
+    // retiring servers never come back, so once too few are left no number
+    // of retries can meet the durability
+    fn unreachable(&self, durability: Durability) -> bool {
+        self.n_servers - self.decommissioning.len() < durability.accepts_needed(self.n_servers)
+    }
+
+    // servers that won't accept this round: they refused it, or they are
+    // being retired and didn't answer it before
+    fn refusals(&self, answers: &[(From, Result<Id, Id>)]) -> usize {
+        let refused = answers.iter().filter(|(_, r)| r.is_err()).count();
+        let retiring = self
+            .decommissioning
+            .iter()
+            .filter(|server| !answers.iter().any(|(from, _)| from == *server))
+            .count();
+        refused + retiring
+    }
+
+    // what this round proposes; retries keep honoring the floor
+    fn proposal(&self) -> Id {
+        self.last_id.max(self.current_floor) + 1
+    }
+
+    fn start_round(&mut self) -> Vec<(To, Message)> {
+        self.resolve_speculation(Speculation::Retracted);
+        if self.unreachable(self.current_durability) {
+            return self.give_up();
+        }
+
+        let new_uuid = self.next_uuid();
+        self.current_uuid = new_uuid;
+        self.current_responses.clear();
+        self.current_promises.clear();
+        self.ballot.0 += 1;
+
+        if let Some(hint) = &self.hint {
+            self.last_id = self.last_id.max(hint.load(Ordering::Relaxed));
+        }
+
+        self.preparing = self.two_phase && !self.preempted;
+        if self.preparing {
+            return self.broadcast(Message::Prepare {
+                uuid: new_uuid,
+                ballot: self.ballot,
+            });
+        }
+        self.broadcast(Message::Request {
+            uuid: new_uuid,
+            ballot: self.ballot,
+            id: self.proposal(),
+        })
+    }
+
+    fn broadcast(&self, message: Message) -> Vec<(To, Message)> {
+        (0..self.n_servers)
+            .filter(|id| !self.decommissioning.contains(id))
+            .map(|id| (id, message.clone()))
+            .collect()
+    }
+
+    fn receive_promise(
+        &mut self,
+        from: From,
+        success: Success,
+        uuid: Uuid,
+        ballot: Ballot,
+        id: Id,
+    ) -> Vec<(To, Message)> {
+        if uuid != self.current_uuid || !self.preparing {
+            return vec![];
+        }
+        if self
+            .current_promises
+            .iter()
+            .any(|(server, _)| *server == from)
+        {
+            return vec![];
+        }
+
+        self.ballot.0 = self.ballot.0.max(ballot.0);
+
+        self.current_promises
+            .push((from, if success { Ok(id) } else { Err(id) }));
+        let mut promised = vec![];
+        let mut refused = vec![];
+        for (_, promise) in &self.current_promises {
+            match promise {
+                Ok(max_id) => promised.push(*max_id),
+                Err(max_id) => refused.push(*max_id),
+            }
+        }
+
+        // preparing only needs a majority, whatever the accept phase needs
+        if promised.len() >= Durability::Quorum.accepts_needed(self.n_servers) {
+            self.preparing = false;
+            self.last_id = self.last_id.max(promised.into_iter().max().unwrap());
+            // a granted promise echoes the ballot we prepared, which
+            // self.ballot may already have been bumped past by a refusal
+            return self.broadcast(Message::Request {
+                uuid,
+                ballot,
+                id: self.proposal(),
+            });
+        }
+
+        // retry as soon as a majority is out of reach rather than waiting for
+        // a majority of refusals, which an even split never produces
+        let needed = Durability::Quorum.accepts_needed(self.n_servers);
+        if self.refusals(&self.current_promises) > self.n_servers - needed {
+            self.last_id = self.last_id.max(refused.into_iter().max().unwrap_or(0));
+            println!("PREEMPTED; BALLOT = {:?}", self.ballot);
+            self.preempted = true;
+            return self.retry();
+        }
+
+        vec![]
+    }
+
+    fn receive(
+        &mut self,
+        from: From,
+        success: Success,
+        uuid: Uuid,
+        ballot: Ballot,
+        id: Id,
+    ) -> Result<Vec<(To, Message)>, Violation> {
+        if uuid != self.current_uuid {
+            return Ok(vec![]);
+        }
+
+        // a server answering the same round twice doesn't count twice
+        if self
+            .current_responses
+            .iter()
+            .any(|(server, _)| *server == from)
+        {
+            return Ok(vec![]);
+        }
+
+        // outrun whichever competing proposer got promised ahead of us
+        self.ballot.0 = self.ballot.0.max(ballot.0);
+
+        if success {
+            verify!(
+                id == self.proposal(),
+                "server {} accepted {} but we proposed {}",
+                from,
+                id,
+                self.proposal()
+            );
+            self.current_responses.push((from, Ok(id)));
+
+            if self.speculated.is_none() {
+                if let Some(hook) = &mut self.on_speculate {
+                    self.speculated = Some(id);
+                    hook(Speculation::Issued(id));
+                }
+            }
+
+            if self
+                .current_responses
+                .iter()
+                .filter(|(_, r)| r.is_ok())
+                .count()
+                >= self.current_durability.accepts_needed(self.n_servers)
+            {
+                debug_assert!(self.last_id < id);
+                self.last_id = id;
+                self.retries = 0;
+                if let Some(hint) = &self.hint {
+                    hint.fetch_max(id, Ordering::Relaxed);
+                }
+                let acceptors = self
+                    .current_responses
+                    .iter()
+                    .filter(|(_, r)| r.is_ok())
+                    .map(|(from, _)| *from)
+                    .collect();
+                self.unrecorded_commit = Some((id, self.current_uuid, acceptors));
+                // idle until the next allocation; late responses are ignored
+                self.current_uuid = Uuid::nil();
+                println!("SUCCESS; ID = {}", id);
+                self.resolve_speculation(Speculation::Confirmed);
+                if let Some(hook) = &mut self.on_commit {
+                    hook(id);
+                }
+            }
+        } else {
+            self.current_responses.push((from, Err(id)));
+
+            if self.refusals(&self.current_responses)
+                > self.current_durability.rejects_tolerated(self.n_servers)
+            {
+                // a ballot reject can come from a server that is behind us,
+                // so only ever move up to the highest max_id reported
+                let rejected_max = self
+                    .current_responses
+                    .iter()
+                    .filter_map(|(_, r)| r.err())
+                    .max()
+                    .unwrap();
+                self.last_id = self.last_id.max(rejected_max);
+                println!("FAILURE; ID = {}", id);
+                return Ok(self.retry());
+            }
+        }
+
+        Ok(vec![])
+    }
+
+    fn receive_batch(
+        &mut self,
+        from: From,
+        responses: Vec<Response>,
+    ) -> Result<Vec<(To, Message)>, Violation> {
+        let mut outbound = vec![];
+        for Response {
+            success,
+            uuid,
+            ballot,
+            id,
+        } in responses
+        {
+            outbound.extend(self.receive(from, success, uuid, ballot, id)?);
+        }
+        Ok(outbound)
+    }
+
+    fn take_commit(&mut self) -> Option<(Id, Uuid, Vec<From>)> {
+        self.unrecorded_commit.take()
+    }
+
+    // quorum is still counted over every server, so skipping one only
+    // costs us its vote, never safety. the vote counts as a refusal of the
+    // round it answered.
+    fn route_around(&mut self, from: From, uuid: Uuid) -> Vec<(To, Message)> {
+        if !self.decommissioning.contains(&from) {
+            self.decommissioning.push(from);
+        }
+        if uuid != self.current_uuid {
+            return vec![];
+        }
+
+        let (answers, durability) = if self.preparing {
+            (&self.current_promises, Durability::Quorum)
+        } else {
+            (&self.current_responses, self.current_durability)
+        };
+        if self.unreachable(durability) {
+            return self.give_up();
+        }
+        if self.refusals(answers) > self.n_servers - durability.accepts_needed(self.n_servers) {
+            println!("FAILURE; ID = {}", self.proposal());
+            return self.retry();
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the round a client just started, as sent to the first server
+    fn request(outbound: &[(To, Message)]) -> (Uuid, Ballot, Id) {
+        match outbound.first() {
+            Some((_, Message::Request { uuid, ballot, id })) => (*uuid, *ballot, *id),
+            other => panic!("expected a Request, got {:?}", other),
+        }
+    }
+
+    // first in, first out until nothing is left; returns how many messages
+    // were delivered
+    fn deliver(computers: &mut [Computer], mut queue: Vec<(From, To, Message)>) -> usize {
+        let mut delivered = 0;
+        while !queue.is_empty() {
+            let (from, to, message) = queue.remove(0);
+            delivered += 1;
+            for (next, message) in computers[to].receive(from, message).unwrap() {
+                queue.push((to, next, message));
+            }
+        }
+        delivered
+    }
+
+    // servers at max_id, then one client
+    fn single_client(n_servers: usize, max_id: Id) -> Vec<Computer> {
+        let mut computers: Vec<Computer> = (0..n_servers)
+            .map(|_| Computer::Server(Server::new_at(max_id)))
+            .collect();
+        computers.push(Computer::Client(Client::new(n_servers, n_servers)));
+        computers
+    }
+
+    fn client(computers: &mut [Computer]) -> &mut Client {
+        match computers.last_mut() {
+            Some(Computer::Client(client)) => client,
+            _ => panic!("the last computer is not a client"),
+        }
+    }
+
+    // starts an allocation on the last computer and runs it to the end
+    fn allocate(
+        computers: &mut [Computer],
+        start: impl FnOnce(&mut Client) -> Vec<(To, Message)>,
+    ) -> usize {
+        let index = computers.len() - 1;
+        let queue = start(client(computers))
+            .into_iter()
+            .map(|(to, message)| (index, to, message))
+            .collect();
+        deliver(computers, queue)
+    }
+
+    // five clients allocate one after another against the same three
+    // servers; returns deliveries per allocation and the ids committed
+    fn take_turns(hint: Option<Arc<AtomicU64>>) -> (Vec<usize>, Vec<Id>) {
+        let mut computers: Vec<Computer> = (0..3)
+            .map(|_| Computer::Server(Server::default()))
+            .collect();
+        for index in 3..8 {
+            let mut client = Client::new(index, 3);
+            if let Some(hint) = &hint {
+                client.share_hint(hint.clone());
+            }
+            computers.push(Computer::Client(client));
+        }
+
+        let (mut deliveries, mut ids) = (vec![], vec![]);
+        for index in 3..8 {
+            let outbound = match &mut computers[index] {
+                Computer::Client(client) => client.generate_requests(),
+                Computer::Server(_) => unreachable!(),
+            };
+            let queue = outbound
+                .into_iter()
+                .map(|(to, message)| (index, to, message))
+                .collect();
+            deliveries.push(deliver(&mut computers, queue));
+            if let Computer::Client(client) = &mut computers[index] {
+                ids.push(client.take_commit().unwrap().0);
+            }
+        }
+        (deliveries, ids)
+    }
+
+    #[test]
+    fn shared_hint_skips_doomed_rounds() {
+        let (alone, alone_ids) = take_turns(None);
+        let (shared, shared_ids) = take_turns(Some(Arc::new(AtomicU64::new(0))));
+
+        // every allocation after the first starts at 1 and is rejected once
+        assert_eq!(alone, vec![6, 12, 12, 12, 12]);
+        // with the hint each one is a single round
+        assert_eq!(shared, vec![6; 5]);
+        assert_eq!(alone_ids, vec![1, 2, 3, 4, 5]);
+        assert_eq!(shared_ids, alone_ids);
+    }
+
+    #[test]
+    fn allocate_at_least_stays_above_the_floor() {
+        let mut computers = single_client(3, 0);
+        allocate(&mut computers, |client| client.allocate_at_least(100));
+        assert_eq!(client(&mut computers).take_commit().unwrap().0, 101);
+
+        // the cluster is already past the floor: just the next id
+        let mut computers = single_client(3, 200);
+        allocate(&mut computers, |client| client.allocate_at_least(50));
+        assert_eq!(client(&mut computers).take_commit().unwrap().0, 201);
+    }
+
+    // a lone server is the whole quorum: one request and one response per id
+    #[test]
+    fn single_server_commits_on_one_accept() {
+        let mut computers = single_client(1, 0);
+        let mut ids = vec![];
+        let mut delivered = 0;
+        for _ in 0..3 {
+            delivered += allocate(&mut computers, Client::generate_requests);
+            assert_eq!(client(&mut computers).retries(), 0);
+            ids.push(client(&mut computers).take_commit().unwrap().0);
+        }
+        assert_eq!(delivered, 6);
+        assert_eq!(ids, vec![1, 2, 3]);
+    }
+
+    // two clients on four servers, under a scheduler that always delivers
+    // A's proposals to servers 0 and 1 and B's to 2 and 3 before anything
+    // else, so every accept phase splits the servers evenly between them.
+    // everything else goes first in, first out. returns the clients that
+    // committed within `budget` deliveries.
+    fn duel(two_phase: bool, budget: usize) -> usize {
+        let mut computers: Vec<Computer> = (0..4)
+            .map(|_| Computer::Server(Server::default()))
+            .collect();
+        let mut queue = vec![];
+        for index in 4..6 {
+            let mut client = Client::new(index, 4);
+            client.two_phase(two_phase);
+            for (to, message) in client.generate_requests() {
+                queue.push((index, to, message));
+            }
+            computers.push(Computer::Client(client));
+        }
+
+        let split = |from: From, to: To, message: &Message| {
+            matches!(message, Message::Request { .. }) && (from == 4) == (to < 2) && to < 4
+        };
+        for _ in 0..budget {
+            if queue.is_empty() {
+                break;
+            }
+            let next = queue
+                .iter()
+                .position(|(from, to, message)| split(*from, *to, message))
+                .unwrap_or(0);
+            let (from, to, message) = queue.remove(next);
+            for (next, message) in computers[to].receive(from, message).unwrap() {
+                queue.push((to, next, message));
+            }
+        }
+
+        computers[4..]
+            .iter()
+            .filter(|computer| match computer {
+                Computer::Client(client) => client.pending_round().is_none(),
+                Computer::Server(_) => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn two_phase_converges_where_one_shot_thrashes() {
+        // one-shot: both propose the same id, each gets half, both retry
+        // with the next id, forever
+        assert_eq!(duel(false, 5_000), 0);
+        // two-phase: the higher ballot wins the prepare everywhere and the
+        // loser learns its id before proposing
+        assert_eq!(duel(true, 5_000), 2);
+    }
+
+    #[test]
+    fn preempted_prepare_falls_back_to_one_shot() {
+        let mut client = Client::new(3, 3);
+        client.two_phase(true);
+        let outbound = client.generate_requests();
+        let uuid = match &outbound[0].1 {
+            Message::Prepare { uuid, .. } => *uuid,
+            other => panic!("expected a Prepare, got {:?}", other),
+        };
+
+        // a higher ballot got to two servers first
+        assert!(client.receive_promise(0, false, uuid, (4, 4), 2).is_empty());
+        let retry = client.receive_promise(1, false, uuid, (4, 4), 3);
+        let (_, ballot, id) = request(&retry);
+        assert!(ballot > (4, 4));
+        assert_eq!(id, 4);
+
+        // and the next allocation prepares again
+        let (uuid, ballot, id) = request(&retry);
+        for server in 0..2 {
+            client.receive(server, true, uuid, ballot, id).unwrap();
+        }
+        assert!(matches!(
+            client.generate_requests()[0].1,
+            Message::Prepare { .. }
+        ));
+    }
+
+    fn counting_hook(client: &mut Client) -> Arc<std::sync::Mutex<Vec<Id>>> {
+        let committed = Arc::new(std::sync::Mutex::new(vec![]));
+        let hook = committed.clone();
+        client.on_commit(move |id| hook.lock().unwrap().push(id));
+        committed
+    }
+
+    #[test]
+    fn on_commit_fires_once_per_committed_id() {
+        let mut client = Client::new(3, 3);
+        let committed = counting_hook(&mut client);
+
+        let (uuid, ballot, id) = request(&client.generate_requests());
+        client.receive(0, true, uuid, ballot, id).unwrap();
+        client.receive(0, true, uuid, ballot, id).unwrap();
+        assert!(committed.lock().unwrap().is_empty());
+        client.receive(1, true, uuid, ballot, id).unwrap();
+        // late and stale successes for the committed round
+        client.receive(2, true, uuid, ballot, id).unwrap();
+        assert_eq!(*committed.lock().unwrap(), vec![id]);
+
+        let (uuid, ballot, _) = request(&client.generate_requests());
+        client.receive(0, false, uuid, ballot, 5).unwrap();
+        client.receive(1, false, uuid, ballot, 5).unwrap();
+        assert_eq!(*committed.lock().unwrap(), vec![id]);
+    }
+
+    // two clients that draw the same round uuids, each with a commit hook
+    fn twin_clients() -> Vec<(Client, Arc<std::sync::Mutex<Vec<Id>>>)> {
+        (0..2)
+            .map(|_| {
+                let mut client = Client::new(3, 3);
+                client.seed_uuids(7);
+                let committed = counting_hook(&mut client);
+                (client, committed)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batched_responses_match_separate_ones() {
+        let mut twins = twin_clients();
+        let (uuid, ballot, id) = request(&twins[0].0.generate_requests());
+        assert_eq!(request(&twins[1].0.generate_requests()), (uuid, ballot, id));
+
+        let accept = Response {
+            success: true,
+            uuid,
+            ballot,
+            id,
+        };
+        let stale = Response {
+            uuid: Uuid::new_v4(),
+            ..accept
+        };
+        // the last accept arrives after the commit
+        let batches = [
+            (0, vec![stale, accept]),
+            (1, vec![accept]),
+            (2, vec![accept]),
+        ];
+
+        let (batched, separate) = twins.split_at_mut(1);
+        let (batched, separate) = (&mut batched[0], &mut separate[0]);
+        for (from, responses) in &batches {
+            let outbound = batched.0.receive_batch(*from, responses.clone()).unwrap();
+            assert!(outbound.is_empty());
+            for response in responses {
+                let outbound = separate
+                    .0
+                    .receive(
+                        *from,
+                        response.success,
+                        response.uuid,
+                        response.ballot,
+                        response.id,
+                    )
+                    .unwrap();
+                assert!(outbound.is_empty());
+            }
+        }
+
+        assert_eq!(batched.0.unrecorded_commit, Some((id, uuid, vec![0, 1])));
+        assert_eq!(batched.0.unrecorded_commit, separate.0.unrecorded_commit);
+        assert_eq!(batched.0.pending_round(), None);
+        assert_eq!(separate.0.pending_round(), None);
+        assert_eq!(*batched.1.lock().unwrap(), vec![id]);
+        assert_eq!(*batched.1.lock().unwrap(), *separate.1.lock().unwrap());
+    }
+
+    // Computer::receive is where a batch is split, so a batch of rejections
+    // from two servers must fail the round and retry it just as the same
+    // responses sent one at a time do
+    #[test]
+    fn batch_delivered_to_a_computer_retries_like_separate_responses() {
+        let mut twins: Vec<Computer> = twin_clients()
+            .into_iter()
+            .map(|(client, _)| Computer::Client(client))
+            .collect();
+        let mut rounds = vec![];
+        for computer in &mut twins {
+            if let Computer::Client(client) = computer {
+                rounds.push(request(&client.generate_requests()));
+            }
+        }
+        assert_eq!(rounds[0], rounds[1]);
+        let (uuid, ballot, _) = rounds[0];
+        let reject = |id| Response {
+            success: false,
+            uuid,
+            ballot,
+            id,
+        };
+        let batches = [(0, vec![reject(5), reject(5)]), (1, vec![reject(7)])];
+
+        let mut batched = vec![];
+        for (from, responses) in &batches {
+            let message = Message::ResponseBatch {
+                responses: responses.clone(),
+            };
+            batched.extend(twins[0].receive(*from, message).unwrap());
+        }
+        let mut separate = vec![];
+        for (from, responses) in &batches {
+            for response in responses {
+                let message = Message::Response {
+                    success: response.success,
+                    uuid: response.uuid,
+                    ballot: response.ballot,
+                    id: response.id,
+                };
+                separate.extend(twins[1].receive(*from, message).unwrap());
+            }
+        }
+
+        assert_eq!(batched.len(), 3);
+        assert_eq!(separate.len(), 3);
+        let (retry, next_ballot, next) = request(&batched);
+        assert_ne!(retry, uuid);
+        assert_eq!(next, 8);
+        assert_eq!(request(&separate), (retry, next_ballot, next));
+
+        // servers never take batches
+        let mut server = Computer::Server(Server::default());
+        let message = Message::ResponseBatch {
+            responses: vec![reject(1)],
+        };
+        assert!(matches!(
+            server.receive(3, message),
+            Err(Violation::Invariant(_))
+        ));
+    }
+
+    #[test]
+    fn retiring_server_counts_toward_abandoning_a_round() {
+        let mut client = Client::new(5, 5);
+        let (uuid, ballot, id) = request(&client.generate_requests());
+        client.receive(0, true, uuid, ballot, id).unwrap();
+        client.receive(1, true, uuid, ballot, id).unwrap();
+        client.receive(2, false, uuid, ballot, 0).unwrap();
+        assert!(client.route_around(4, uuid).is_empty());
+
+        // 2 accepts, 2 refusals and a retiring server: no quorum is left
+        let retry = client.receive(3, false, uuid, ballot, 0).unwrap();
+        let (next, ..) = request(&retry);
+        assert_ne!(next, uuid);
+        assert!(retry.iter().all(|(to, _)| *to != 4));
+    }
+
+    #[test]
+    fn all_durability_gives_up_once_a_server_retires() {
+        let mut client = Client::new(3, 3);
+        let (uuid, ..) = request(&client.allocate(Durability::All));
+        assert!(client.route_around(2, uuid).is_empty());
+        assert_eq!(client.error(), Some(ClientError::QuorumUnreachable));
+        assert_eq!(client.pending_round(), None);
+
+        // and refuses later allocations up front
+        assert!(client.allocate(Durability::All).is_empty());
+        assert_eq!(client.error(), Some(ClientError::QuorumUnreachable));
+        assert_eq!(request(&client.allocate(Durability::Quorum)).2, 1);
+    }
+
+    #[test]
+    fn even_split_abandons_the_round() {
+        let mut client = Client::new(4, 4);
+        let (uuid, ballot, id) = request(&client.generate_requests());
+        client.receive(0, true, uuid, ballot, id).unwrap();
+        client.receive(1, true, uuid, ballot, id).unwrap();
+        client.receive(2, false, uuid, ballot, id).unwrap();
+        let retry = client.receive(3, false, uuid, ballot, id).unwrap();
+        assert_eq!(retry.len(), 4);
+    }
+
+    #[test]
+    fn all_durability_waits_for_every_server() {
+        let mut client = Client::new(3, 3);
+        let committed = counting_hook(&mut client);
+        let (uuid, ballot, id) = request(&client.allocate(Durability::All));
+        client.receive(0, true, uuid, ballot, id).unwrap();
+        client.receive(1, true, uuid, ballot, id).unwrap();
+        // a quorum, but the third server is down
+        assert!(committed.lock().unwrap().is_empty());
+        assert!(client.pending_round().is_some());
+
+        client.receive(2, true, uuid, ballot, id).unwrap();
+        assert_eq!(*committed.lock().unwrap(), vec![id]);
+
+        // and a single reject fails the round instead of committing
+        let (uuid, ballot, id) = request(&client.allocate(Durability::All));
+        client.receive(0, true, uuid, ballot, id).unwrap();
+        let retry = client.receive(1, false, uuid, ballot, id).unwrap();
+        assert_eq!(retry.len(), 3);
+    }
+
+    #[test]
+    fn restore_refuses_to_move_max_id_backward() {
+        let mut server = Server::new_at(10);
+        assert_eq!(
+            server.restore(9),
+            Err(SafetyViolation {
+                max_id: 10,
+                attempted: 9
+            })
+        );
+        assert_eq!(server.max_id(), 10);
+        assert_eq!(server.restore(12), Ok(()));
+        assert_eq!(server.max_id(), 12);
+    }
+
+    #[test]
+    fn strict_next_refuses_gaps_greater_than_does_not() {
+        let mut strict = Server::with_policy(StrictNext);
+        let mut greater = Server::with_policy(GreaterThan);
+        for server in [&mut strict, &mut greater] {
+            server.propose(3, Uuid::new_v4(), (1, 3), 1).unwrap();
+        }
+
+        let gap = |server: &mut Server| match server.propose(3, Uuid::new_v4(), (2, 3), 3).unwrap()
+            [0]
+        .1
+        {
+            Message::Response { success, .. } => success,
+            ref other => panic!("unexpected {:?}", other),
+        };
+        assert!(!gap(&mut strict));
+        assert!(gap(&mut greater));
+        assert_eq!((strict.max_id(), greater.max_id()), (1, 3));
+    }
+
+    fn speculation_log(client: &mut Client) -> Arc<std::sync::Mutex<Vec<Speculation>>> {
+        let log = Arc::new(std::sync::Mutex::new(vec![]));
+        let hook = log.clone();
+        client.speculate(move |speculation| hook.lock().unwrap().push(speculation));
+        log
+    }
+
+    #[test]
+    fn failed_speculation_is_retracted_and_not_committed() {
+        let mut client = Client::new(3, 3);
+        let log = speculation_log(&mut client);
+        let committed = counting_hook(&mut client);
+
+        let (uuid, ballot, id) = request(&client.generate_requests());
+        client.receive(0, true, uuid, ballot, id).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec![Speculation::Issued(id)]);
+        client.receive(1, false, uuid, ballot, id).unwrap();
+        client.receive(2, false, uuid, ballot, id).unwrap();
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![Speculation::Issued(id), Speculation::Retracted(id)]
+        );
+        assert!(committed.lock().unwrap().is_empty());
+        assert!(client.take_commit().is_none());
+        assert_eq!(client.speculation_stats(), (0, 1));
+    }
+
+    #[test]
+    fn abandoned_speculation_is_retracted_for_good() {
+        let mut client = Client::new(3, 3);
+        let log = speculation_log(&mut client);
+
+        let (uuid, ballot, id) = request(&client.generate_requests());
+        client.receive(0, true, uuid, ballot, id).unwrap();
+        client.abandon();
+        assert_eq!(client.pending_round(), None);
+        assert_eq!(client.error(), Some(ClientError::Abandoned));
+
+        // the rest of the quorum arrives too late to confirm it
+        client.receive(1, true, uuid, ballot, id).unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![Speculation::Issued(id), Speculation::Retracted(id)]
+        );
+        assert!(client.take_commit().is_none());
+    }
+
+    // verify! isn't compiled out, so this holds under --release too
+    #[test]
+    fn mismatched_accept_is_a_violation_not_a_panic() {
+        let mut client = Computer::Client(Client::new(3, 3));
+        let outbound = match &mut client {
+            Computer::Client(client) => client.generate_requests(),
+            Computer::Server(_) => unreachable!(),
+        };
+        let (uuid, ballot, id) = request(&outbound);
+
+        let accepted = Message::Response {
+            success: true,
+            uuid,
+            ballot,
+            id: id + 1,
+        };
+        assert!(matches!(
+            client.receive(0, accepted),
+            Err(Violation::Invariant(_))
+        ));
+    }
+
+    #[test]
+    fn client_gives_up_after_max_retries() {
+        let mut client = Client::new(5, 5);
+        client.max_retries(Some(3));
+
+        // only a minority of servers is reachable; a timeout retries each
+        // round, the way the channel transport does
+        let mut outbound = client.generate_requests();
+        for _ in 0..3 {
+            let (uuid, ballot, id) = request(&outbound);
+            client.receive(0, true, uuid, ballot, id).unwrap();
+            client.receive(1, true, uuid, ballot, id).unwrap();
+            assert_eq!(client.error(), None);
+            outbound = client.retry();
+        }
+        assert_eq!(client.retries(), 3);
+        assert!(client.retry().is_empty());
+        assert_eq!(client.error(), Some(ClientError::QuorumUnreachable));
+        assert_eq!(client.pending_round(), None);
+
+        // a later allocation starts counting again and can succeed
+        let (uuid, ballot, id) = request(&client.generate_requests());
+        assert_eq!((client.retries(), client.error()), (0, None));
+        for server in 0..3 {
+            client.receive(server, true, uuid, ballot, id).unwrap();
+        }
+        assert!(client.take_commit().is_some());
+    }
+
+    #[test]
+    fn pending_prepares_are_freed() {
+        let mut server = Server::default();
+        let uuids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        // the proposal arriving frees its prepare
+        server.prepare(3, uuids[0], (1, 3));
+        assert_eq!(server.pending().len(), 1);
+        server.propose(3, uuids[0], (1, 3), 1).unwrap();
+        assert!(server.pending().is_empty());
+
+        // a higher ballot supersedes a prepare whose proposal never came
+        server.prepare(3, uuids[1], (2, 3));
+        server.prepare(4, uuids[2], (2, 4));
+        assert_eq!(server.pending().len(), 1);
+        assert_eq!(server.pending()[0].uuid, uuids[2]);
+
+        // and retiring frees whatever is left
+        server.begin_decommission();
+        assert!(server.pending().is_empty());
+        assert!(matches!(
+            server.propose(4, uuids[2], (2, 4), 2).unwrap()[0].1,
+            Message::Decommissioning { .. }
+        ));
+        assert!(server.pending().is_empty());
+    }
+
+    #[test]
+    fn a_clients_next_round_frees_its_last_prepare() {
+        let mut server = Server::default();
+        server.prepare(4, Uuid::new_v4(), (1, 4));
+        // preempted elsewhere, the client prepares again with a new round
+        let retry = Uuid::new_v4();
+        server.prepare(4, retry, (2, 4));
+        assert_eq!(server.pending().len(), 1);
+        assert_eq!(server.pending()[0].uuid, retry);
+    }
+
+    #[test]
+    fn prepare_overtaken_by_its_proposal_is_not_held() {
+        let mut server = Server::default();
+        let uuid = Uuid::new_v4();
+        server.propose(4, uuid, (1, 4), 1).unwrap();
+        server.prepare(4, uuid, (1, 4));
+        assert!(server.pending().is_empty());
+    }
+
+    #[test]
+    fn lower_ballot_is_rejected_even_with_a_higher_id() {
+        let mut server = Server::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let accepted = server.propose(4, b, (1, 4), 1).unwrap();
+        assert!(matches!(
+            accepted[0].1,
+            Message::Response { success: true, .. }
+        ));
+
+        // by id alone this would be accepted, 2 > 1
+        let rejected = server.propose(3, a, (1, 3), 2).unwrap();
+        match rejected[0].1 {
+            Message::Response {
+                success,
+                ballot,
+                id,
+                ..
+            } => {
+                assert!(!success);
+                assert_eq!(ballot, (1, 4));
+                assert_eq!(id, 1);
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(server.max_id(), 1);
+    }
+
+    #[test]
+    fn rejected_client_outruns_the_higher_ballot() {
+        let mut client = Client::new(3, 1);
+        let (uuid, ballot, id) = request(&client.generate_requests());
+        assert_eq!(ballot, (1, 3));
+
+        let retry = client.receive(0, false, uuid, (5, 4), id).unwrap();
+        let (_, ballot, _) = request(&retry);
+        assert!(ballot > (5, 4));
+    }
+
+    #[test]
+    fn competing_proposers_commit_distinct_ids() {
+        let mut computers: Vec<Computer> = (0..3)
+            .map(|_| Computer::Server(Server::default()))
+            .collect();
+        computers.push(Computer::Client(Client::new(3, 3)));
+        computers.push(Computer::Client(Client::new(4, 3)));
+        let mut hooks = vec![];
+        for computer in &mut computers[3..] {
+            if let Computer::Client(client) = computer {
+                hooks.push(counting_hook(client));
+            }
+        }
+
+        // both rounds reach every server before any response comes back,
+        // interleaved so each server sees the proposals in a different order
+        let mut queue = vec![];
+        for (index, computer) in computers.iter_mut().enumerate().skip(3) {
+            if let Computer::Client(client) = computer {
+                for (to, message) in client.generate_requests() {
+                    queue.push((index, to, message));
+                }
+            }
+        }
+        queue.swap(0, 3);
+        deliver(&mut computers, queue);
+
+        let mut committed: Vec<Id> = hooks
+            .iter()
+            .flat_map(|hook| hook.lock().unwrap().clone())
+            .collect();
+        assert_eq!(committed.len(), 2);
+        committed.sort_unstable();
+        committed.dedup();
+        assert_eq!(committed.len(), 2);
+    }
+
+    #[test]
+    fn failure_never_moves_last_id_backward() {
+        let mut client = Client::new(3, 3);
+        let (uuid, ballot, id) = request(&client.generate_requests());
+
+        // one server is ahead, another rejects our ballot from behind
+        client.receive(0, false, uuid, ballot, 7).unwrap();
+        let retry = client.receive(1, false, uuid, (2, 4), 0).unwrap();
+
+        let (_, _, next) = request(&retry);
+        assert!(next > id);
+        assert_eq!(next, 8);
+    }
+}
@@ -0,0 +1,132 @@
+// destinations for committed ids, so consumers don't have to poll the cluster
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use crate::Id;
+
+// receives every committed id exactly once, in commit order. after an
+// error it is sent nothing more; see Cluster::sink_error.
+pub trait IdSink: std::fmt::Debug {
+    fn emit(&mut self, id: Id, client: usize) -> io::Result<()>;
+}
+
+// forwards (id, client) pairs; ids committed after the receiver hangs up are
+// dropped
+#[derive(Debug)]
+pub struct ChannelSink(pub Sender<(Id, usize)>);
+
+impl IdSink for ChannelSink {
+    fn emit(&mut self, id: Id, client: usize) -> io::Result<()> {
+        let _ = self.0.send((id, client));
+        Ok(())
+    }
+}
+
+// appends one id per line
+#[derive(Debug)]
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<FileSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink { file })
+    }
+}
+
+impl IdSink for FileSink {
+    fn emit(&mut self, id: Id, _client: usize) -> io::Result<()> {
+        writeln!(self.file, "{}", id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use super::*;
+    use crate::Cluster;
+
+    #[derive(Debug, Default, Clone)]
+    struct VecSink(Arc<Mutex<Vec<(Id, usize)>>>);
+
+    impl IdSink for VecSink {
+        fn emit(&mut self, id: Id, client: usize) -> io::Result<()> {
+            self.0.lock().unwrap().push((id, client));
+            Ok(())
+        }
+    }
+
+    fn seeded_cluster() -> Cluster {
+        let mut cluster = Cluster::new(5, 6).unwrap();
+        cluster.seed(8);
+        cluster
+    }
+
+    #[test]
+    fn sink_gets_every_commit_once_in_order() {
+        let sink = VecSink::default();
+        let mut cluster = seeded_cluster();
+        cluster.sink(sink.clone());
+        cluster.run().unwrap();
+
+        let log: Vec<(Id, usize)> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| (commit.id, commit.client))
+            .collect();
+        assert_eq!(log.len(), 6);
+        assert_eq!(*sink.0.lock().unwrap(), log);
+    }
+
+    #[test]
+    fn channel_sink_forwards_until_hung_up() {
+        let (tx, rx) = mpsc::channel();
+        let mut cluster = seeded_cluster();
+        cluster.sink(ChannelSink(tx));
+        cluster.run().unwrap();
+        assert_eq!(rx.try_iter().count(), 6);
+    }
+
+    #[test]
+    fn file_sink_appends_one_id_per_line() {
+        let path = std::env::temp_dir().join(format!("id-gen-sink-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut cluster = seeded_cluster();
+        cluster.sink(FileSink::open(&path).unwrap());
+        cluster.run().unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let ids: Vec<Id> = written.lines().map(|line| line.parse().unwrap()).collect();
+        let log: Vec<Id> = cluster
+            .commit_log()
+            .iter()
+            .map(|commit| commit.id)
+            .collect();
+        assert_eq!(ids, log);
+    }
+
+    // a disk that fills up mid-run doesn't take the cluster down with it
+    #[test]
+    fn failed_write_is_reported_not_panicked() {
+        let path = std::env::temp_dir().join(format!("id-gen-sink-ro-{}", std::process::id()));
+        File::create(&path).unwrap();
+        // read-only, so every append fails
+        let sink = FileSink {
+            file: File::open(&path).unwrap(),
+        };
+
+        let mut cluster = seeded_cluster();
+        cluster.sink(sink);
+        cluster.run().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(cluster.sink_error().is_some());
+        assert_eq!(cluster.commit_log().len(), 6);
+    }
+}
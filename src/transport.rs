@@ -0,0 +1,31 @@
+// the part every way of connecting computers has in common, so the protocol
+// is driven the same way in the simulator and over channels
+use crate::{Computer, From, Message, To, Violation};
+
+// one node's view of the network
+pub trait Transport {
+    fn send(&mut self, to: To, message: Message);
+
+    // None when nothing arrived: the transport is closed, or it stopped
+    // waiting
+    fn recv(&mut self) -> Option<(From, Message)>;
+}
+
+pub fn send_all(transport: &mut impl Transport, outbound: Vec<(To, Message)>) {
+    for (to, message) in outbound {
+        transport.send(to, message);
+    }
+}
+
+// delivers the next message, if any, and sends out whatever the computer
+// answers with. returns whether a message was delivered.
+pub fn step(computer: &mut Computer, transport: &mut impl Transport) -> Result<bool, Violation> {
+    match transport.recv() {
+        Some((from, message)) => {
+            let outbound = computer.receive(from, message)?;
+            send_all(transport, outbound);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}